@@ -0,0 +1,211 @@
+use crate::FeedType;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs;
+use std::net::IpAddr;
+
+/// What an authenticated client is permitted to do: which feeds it may
+/// connect to and which subjects it may subscribe to. `*` in either set
+/// means "everything".
+#[derive(Debug, Clone)]
+pub struct Acl {
+    feeds: HashSet<String>,
+    subjects: HashSet<String>,
+}
+
+impl Acl {
+    fn all() -> Self {
+        Self {
+            feeds: HashSet::from(["*".to_string()]),
+            subjects: HashSet::from(["*".to_string()]),
+        }
+    }
+
+    fn parse(feeds: &str, subjects: &str) -> Self {
+        Self {
+            feeds: feeds.split('|').map(str::to_ascii_lowercase).collect(),
+            subjects: subjects.split('|').map(str::to_string).collect(),
+        }
+    }
+
+    pub fn allows_feed(&self, feed: &FeedType) -> bool {
+        self.feeds.contains("*") || self.feeds.contains(&feed.name().to_ascii_lowercase())
+    }
+
+    pub fn allows_subject(&self, subject: &str) -> bool {
+        self.subjects.contains("*") || self.subjects.contains(subject)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Credential {
+    secret: String,
+    acl: Acl,
+}
+
+/// Validates client-supplied key/secret pairs against a configured
+/// credential store, rather than accepting any `action: auth` message.
+pub struct CredentialStore {
+    credentials: HashMap<String, Credential>,
+}
+
+impl CredentialStore {
+    /// Seed from `PROXY_CLIENT_CREDENTIALS` (`key:secret:feeds:subjects`
+    /// entries separated by `;`; `feeds`/`subjects` are `|`-separated,
+    /// `*` meaning "everything") or `PROXY_CLIENT_CREDENTIALS_FILE` (same
+    /// format, one entry per line). Falls back to a single credential
+    /// built from the upstream `ALPACA_API_KEY`/`ALPACA_SECRET_KEY` pair
+    /// granted access to everything, so an unconfigured deployment keeps
+    /// working exactly as it did before this store existed.
+    pub fn from_env() -> Self {
+        let raw = if let Ok(path) = env::var("PROXY_CLIENT_CREDENTIALS_FILE") {
+            match fs::read_to_string(&path) {
+                Ok(contents) => contents.lines().collect::<Vec<_>>().join(";"),
+                Err(e) => {
+                    eprintln!("Failed to read {}: {}", path, e);
+                    String::new()
+                }
+            }
+        } else {
+            env::var("PROXY_CLIENT_CREDENTIALS").unwrap_or_default()
+        };
+
+        let mut credentials = HashMap::new();
+        for entry in raw.split(';').map(str::trim).filter(|e| !e.is_empty()) {
+            let fields: Vec<&str> = entry.split(':').collect();
+            let [key, secret, feeds, subjects] = fields[..] else {
+                eprintln!("Skipping malformed PROXY_CLIENT_CREDENTIALS entry: {}", entry);
+                continue;
+            };
+            credentials.insert(
+                key.to_string(),
+                Credential {
+                    secret: secret.to_string(),
+                    acl: Acl::parse(feeds, subjects),
+                },
+            );
+        }
+
+        if credentials.is_empty() {
+            if let (Ok(key), Ok(secret)) = (env::var("ALPACA_API_KEY"), env::var("ALPACA_SECRET_KEY"))
+            {
+                credentials.insert(
+                    key,
+                    Credential {
+                        secret,
+                        acl: Acl::all(),
+                    },
+                );
+            }
+        }
+
+        Self { credentials }
+    }
+
+    pub fn authenticate(&self, key: &str, secret: &str) -> Option<Acl> {
+        self.credentials
+            .get(key)
+            .filter(|credential| constant_time_eq(credential.secret.as_bytes(), secret.as_bytes()))
+            .map(|credential| credential.acl.clone())
+    }
+}
+
+/// Compares two byte slices in constant time with respect to their
+/// contents, so a client probing `secret` over the network can't recover
+/// it a byte at a time from response-time differences. Unequal lengths
+/// still short-circuit, since the length of a secret isn't itself secret.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// A minimal, dependency-free CIDR allowlist: IPv4 entries support
+/// `a.b.c.d/prefix` masking; anything else (including all of IPv6) is
+/// matched as an exact address.
+pub struct CidrAllowlist {
+    entries: Vec<(IpAddr, u8)>,
+}
+
+impl CidrAllowlist {
+    pub fn from_env() -> Option<Self> {
+        let raw = env::var("PROXY_ALLOWED_CIDRS").ok()?;
+        let entries: Vec<(IpAddr, u8)> = raw
+            .split(',')
+            .map(str::trim)
+            .filter(|e| !e.is_empty())
+            .filter_map(|entry| {
+                let (addr, prefix) = match entry.split_once('/') {
+                    Some((addr, prefix)) => (addr, prefix.parse().ok()?),
+                    None => (entry, 32),
+                };
+                addr.parse::<IpAddr>().ok().map(|addr| (addr, prefix))
+            })
+            .collect();
+
+        if entries.is_empty() {
+            None
+        } else {
+            Some(Self { entries })
+        }
+    }
+
+    pub fn allows(&self, addr: IpAddr) -> bool {
+        self.entries
+            .iter()
+            .any(|(network, prefix)| matches_cidr(addr, *network, *prefix))
+    }
+}
+
+fn matches_cidr(addr: IpAddr, network: IpAddr, prefix: u8) -> bool {
+    match (addr, network) {
+        (IpAddr::V4(addr), IpAddr::V4(network)) => {
+            let mask = if prefix == 0 {
+                0
+            } else {
+                u32::MAX << (32 - prefix.min(32))
+            };
+            u32::from(addr) & mask == u32::from(network) & mask
+        }
+        _ => addr == network,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_acl_wildcard_allows_everything() {
+        let acl = Acl::all();
+        assert!(acl.allows_feed(&FeedType::Paper));
+        assert!(acl.allows_feed(&FeedType::Live));
+        assert!(acl.allows_subject("trade_updates"));
+    }
+
+    #[test]
+    fn test_acl_scoped_to_configured_feeds_and_subjects() {
+        let acl = Acl::parse("paper", "trade_updates");
+        assert!(acl.allows_feed(&FeedType::Paper));
+        assert!(!acl.allows_feed(&FeedType::Live));
+        assert!(acl.allows_subject("trade_updates"));
+        assert!(!acl.allows_subject("quotes.AAPL"));
+    }
+
+    #[test]
+    fn test_constant_time_eq_matches_and_rejects() {
+        assert!(constant_time_eq(b"s3cr3t", b"s3cr3t"));
+        assert!(!constant_time_eq(b"s3cr3t", b"wrong!"));
+        assert!(!constant_time_eq(b"s3cr3t", b"short"));
+    }
+
+    #[test]
+    fn test_cidr_v4_prefix_match() {
+        let allowlist = CidrAllowlist {
+            entries: vec![("10.0.0.0".parse().unwrap(), 8)],
+        };
+        assert!(allowlist.allows("10.1.2.3".parse().unwrap()));
+        assert!(!allowlist.allows("11.0.0.1".parse().unwrap()));
+    }
+}
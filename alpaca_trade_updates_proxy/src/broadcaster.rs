@@ -0,0 +1,585 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, watch, Mutex, Notify};
+use tokio::time::sleep;
+use uuid::Uuid;
+
+pub type ClientId = Uuid;
+
+/// Channel depth for `Drop`-policy clients: newest message is discarded
+/// once this many are queued.
+const DROP_CHANNEL_CAPACITY: usize = 256;
+/// Channel depth for `Reliable`-policy clients before they start counting
+/// toward eviction.
+const RELIABLE_CHANNEL_CAPACITY: usize = 1024;
+/// Evict a `Reliable` client after this many consecutive failed sends.
+const RELIABLE_MAX_CONSECUTIVE_FAILURES: u32 = 5;
+/// Evict a `Reliable` client if it hasn't accepted a message in this long,
+/// even if sends keep trickling through just slowly enough to avoid the
+/// consecutive-failure threshold.
+const RELIABLE_STALENESS_DEADLINE: Duration = Duration::from_secs(30);
+/// How often the background sweep checks for stale `Reliable` clients.
+const STALENESS_SWEEP_INTERVAL: Duration = Duration::from_secs(5);
+/// Log the achieved MessagePack compression ratio every Nth encoded frame
+/// rather than every one, since a busy feed would otherwise flood stdout.
+const COMPRESSION_LOG_INTERVAL: u64 = 100;
+
+/// Per-client channel back-pressure policy, modeled on a message bus.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QosPolicy {
+    /// Drop the newest message when the client's channel is full. This is
+    /// the proxy's original, implicit behavior.
+    Drop,
+    /// Keep a single-slot mailbox that always holds only the most recent
+    /// message - ideal for quote/snapshot-style feeds where staleness
+    /// beats queuing.
+    Latest,
+    /// Buffer up to the channel's capacity; force-disconnect the client
+    /// once it stalls past `RELIABLE_MAX_CONSECUTIVE_FAILURES` or
+    /// `RELIABLE_STALENESS_DEADLINE` rather than silently dropping.
+    Reliable,
+}
+
+impl QosPolicy {
+    /// Parse a QoS policy name (`drop`, `latest`, `reliable`), falling back
+    /// to `Drop` for anything missing or unrecognized.
+    pub fn parse(value: Option<&str>) -> Self {
+        match value.map(str::to_ascii_lowercase).as_deref() {
+            Some("latest") => QosPolicy::Latest,
+            Some("reliable") => QosPolicy::Reliable,
+            _ => QosPolicy::Drop,
+        }
+    }
+}
+
+/// Wire encoding a client negotiates at connect time (path suffix or
+/// auth-message field, mirroring how `QosPolicy` is chosen). `broadcast`
+/// transcodes a message into each encoding actually in use at most once,
+/// then shares the result across every client that negotiated it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientEncoding {
+    /// The original JSON text frame, unchanged.
+    Json,
+    /// MessagePack, sent as a binary frame.
+    MsgPack,
+}
+
+impl ClientEncoding {
+    /// Parse an encoding name (`msgpack`), falling back to `Json` for
+    /// anything missing or unrecognized.
+    pub fn parse(value: Option<&str>) -> Self {
+        match value.map(str::to_ascii_lowercase).as_deref() {
+            Some("msgpack") => ClientEncoding::MsgPack,
+            _ => ClientEncoding::Json,
+        }
+    }
+}
+
+/// A message ready to go out on the wire. Built once per distinct
+/// `ClientEncoding` by `broadcast` and cheaply cloned (an `Arc` bump, not a
+/// copy) for every matching client, rather than re-encoded per client.
+#[derive(Clone)]
+pub enum Frame {
+    Text(Arc<str>),
+    Binary(Arc<[u8]>),
+}
+
+enum ClientChannel {
+    Bounded(mpsc::Sender<Frame>),
+    Latest(watch::Sender<Option<Frame>>),
+}
+
+/// The receiving half handed back to the per-client task; mirrors whichever
+/// channel kind `QosPolicy` selected so callers don't need to match on it.
+pub enum ClientReceiver {
+    Bounded(mpsc::Receiver<Frame>),
+    Latest(watch::Receiver<Option<Frame>>),
+}
+
+impl ClientReceiver {
+    pub async fn recv(&mut self) -> Option<Frame> {
+        match self {
+            ClientReceiver::Bounded(rx) => rx.recv().await,
+            ClientReceiver::Latest(rx) => loop {
+                if rx.changed().await.is_err() {
+                    return None;
+                }
+                if let Some(frame) = rx.borrow_and_update().clone() {
+                    return Some(frame);
+                }
+            },
+        }
+    }
+}
+
+enum SendOutcome {
+    Sent,
+    Dropped,
+    Disconnected,
+}
+
+/// Bookkeeping kept alongside each client's channel: counters the request
+/// asked for (sent/dropped/last-success) plus the running consecutive
+/// failure count `Reliable` eviction is judged against.
+struct ClientEntry {
+    channel: ClientChannel,
+    qos: QosPolicy,
+    encoding: ClientEncoding,
+    sent: u64,
+    dropped: u64,
+    consecutive_failures: u32,
+    last_success: Instant,
+    /// Fired when this client is evicted, so the connection task blocked on
+    /// reading from the client socket - which never sees a dropped channel -
+    /// learns to tear itself down too instead of leaking until the client
+    /// notices on its own.
+    disconnect: Arc<Notify>,
+}
+
+impl ClientEntry {
+    fn send(&mut self, frame: Frame) -> SendOutcome {
+        let sent = match &self.channel {
+            ClientChannel::Bounded(tx) => match tx.try_send(frame) {
+                Ok(()) => true,
+                Err(mpsc::error::TrySendError::Full(_)) => false,
+                Err(mpsc::error::TrySendError::Closed(_)) => return SendOutcome::Disconnected,
+            },
+            ClientChannel::Latest(tx) => {
+                if tx.send(Some(frame)).is_err() {
+                    return SendOutcome::Disconnected;
+                }
+                true
+            }
+        };
+
+        if sent {
+            self.sent += 1;
+            self.consecutive_failures = 0;
+            self.last_success = Instant::now();
+            SendOutcome::Sent
+        } else {
+            self.dropped += 1;
+            self.consecutive_failures += 1;
+            SendOutcome::Dropped
+        }
+    }
+
+    fn is_stalled_reliable_client(&self) -> bool {
+        self.qos == QosPolicy::Reliable
+            && (self.consecutive_failures >= RELIABLE_MAX_CONSECUTIVE_FAILURES
+                || self.last_success.elapsed() > RELIABLE_STALENESS_DEADLINE)
+    }
+}
+
+/// One node of the subject trie. Concrete subjects like `trade_updates` or
+/// `quotes.AAPL` are split on `.` and walked token by token. Literal
+/// children key by exact token; `*` matches exactly one token; `>` swallows
+/// every remaining token and terminates the match at that node.
+#[derive(Default)]
+struct TrieNode {
+    children: HashMap<String, TrieNode>,
+    wildcard_one: Option<Box<TrieNode>>,
+    wildcard_tail: HashSet<ClientId>,
+    subscribers: HashSet<ClientId>,
+}
+
+impl TrieNode {
+    fn insert(&mut self, tokens: &[&str], client_id: ClientId) {
+        match tokens.split_first() {
+            None => {
+                self.subscribers.insert(client_id);
+            }
+            Some((&"*", rest)) => {
+                self.wildcard_one
+                    .get_or_insert_with(Box::default)
+                    .insert(rest, client_id);
+            }
+            Some((&">", _rest)) => {
+                self.wildcard_tail.insert(client_id);
+            }
+            Some((token, rest)) => {
+                self.children
+                    .entry((*token).to_string())
+                    .or_default()
+                    .insert(rest, client_id);
+            }
+        }
+    }
+
+    fn remove(&mut self, tokens: &[&str], client_id: ClientId) {
+        match tokens.split_first() {
+            None => {
+                self.subscribers.remove(&client_id);
+            }
+            Some((&"*", rest)) => {
+                if let Some(node) = self.wildcard_one.as_mut() {
+                    node.remove(rest, client_id);
+                }
+            }
+            Some((&">", _rest)) => {
+                self.wildcard_tail.remove(&client_id);
+            }
+            Some((token, rest)) => {
+                if let Some(node) = self.children.get_mut(*token) {
+                    node.remove(rest, client_id);
+                }
+            }
+        }
+    }
+
+    /// Walk a concrete subject's tokens, collecting every matching
+    /// subscriber: exact literal match, `*` at this level, and `>` which
+    /// short-circuits to everything under it regardless of how many
+    /// tokens remain.
+    fn collect_matches(&self, tokens: &[&str], out: &mut HashSet<ClientId>) {
+        out.extend(&self.wildcard_tail);
+
+        match tokens.split_first() {
+            None => out.extend(&self.subscribers),
+            Some((token, rest)) => {
+                if let Some(child) = self.children.get(*token) {
+                    child.collect_matches(rest, out);
+                }
+                if let Some(wildcard) = &self.wildcard_one {
+                    wildcard.collect_matches(rest, out);
+                }
+            }
+        }
+    }
+}
+
+/// Hierarchical, NATS-style subject trie: routes a concrete subject (e.g.
+/// `trade_updates`, `quotes.AAPL`) to only the clients subscribed to it or
+/// to a matching wildcard subject (`quotes.*`, `bars.>`). Keeps an O(1)
+/// reverse index from `ClientId` to its subscribed subjects so client
+/// teardown doesn't require a full trie walk.
+#[derive(Default)]
+struct SubjectTrie {
+    root: TrieNode,
+    by_client: HashMap<ClientId, HashSet<String>>,
+}
+
+impl SubjectTrie {
+    fn subscribe(&mut self, client_id: ClientId, subject: &str) {
+        let tokens: Vec<&str> = subject.split('.').collect();
+        self.root.insert(&tokens, client_id);
+        self.by_client
+            .entry(client_id)
+            .or_default()
+            .insert(subject.to_string());
+    }
+
+    fn unsubscribe(&mut self, client_id: ClientId, subject: &str) {
+        let tokens: Vec<&str> = subject.split('.').collect();
+        self.root.remove(&tokens, client_id);
+        if let Some(subjects) = self.by_client.get_mut(&client_id) {
+            subjects.remove(subject);
+        }
+    }
+
+    fn remove_client(&mut self, client_id: ClientId) {
+        if let Some(subjects) = self.by_client.remove(&client_id) {
+            for subject in subjects {
+                let tokens: Vec<&str> = subject.split('.').collect();
+                self.root.remove(&tokens, client_id);
+            }
+        }
+    }
+
+    fn matches(&self, subject: &str) -> HashSet<ClientId> {
+        let tokens: Vec<&str> = subject.split('.').collect();
+        let mut out = HashSet::new();
+        self.root.collect_matches(&tokens, &mut out);
+        out
+    }
+}
+
+/// Subject-filtered broadcaster: each client subscribes to one or more
+/// subjects and only messages whose extracted subject matches are forwarded
+/// to it, rather than every upstream message going to every client.
+pub struct Broadcaster {
+    name: String,
+    clients: Mutex<HashMap<ClientId, ClientEntry>>,
+    subjects: Mutex<SubjectTrie>,
+    msgpack_frames_encoded: AtomicU64,
+}
+
+impl Broadcaster {
+    pub fn new(name: impl Into<String>) -> Arc<Self> {
+        let broadcaster = Arc::new(Self {
+            name: name.into(),
+            clients: Mutex::new(HashMap::new()),
+            subjects: Mutex::new(SubjectTrie::default()),
+            msgpack_frames_encoded: AtomicU64::new(0),
+        });
+
+        // Sends alone can't catch a Reliable client that stalls slowly
+        // enough to dodge the consecutive-failure threshold, so sweep for
+        // staleness on a timer too. Holds only a Weak ref so the sweep
+        // task doesn't keep the broadcaster alive past its last Arc.
+        let weak = Arc::downgrade(&broadcaster);
+        tokio::spawn(async move {
+            loop {
+                sleep(STALENESS_SWEEP_INTERVAL).await;
+                match weak.upgrade() {
+                    Some(broadcaster) => broadcaster.evict_stalled_clients().await,
+                    None => return,
+                }
+            }
+        });
+
+        broadcaster
+    }
+
+    pub async fn add_client(
+        &self,
+        client_id: ClientId,
+        qos: QosPolicy,
+        encoding: ClientEncoding,
+    ) -> (ClientReceiver, Arc<Notify>) {
+        let (channel, receiver) = match qos {
+            QosPolicy::Drop => {
+                let (tx, rx) = mpsc::channel(DROP_CHANNEL_CAPACITY);
+                (ClientChannel::Bounded(tx), ClientReceiver::Bounded(rx))
+            }
+            QosPolicy::Reliable => {
+                let (tx, rx) = mpsc::channel(RELIABLE_CHANNEL_CAPACITY);
+                (ClientChannel::Bounded(tx), ClientReceiver::Bounded(rx))
+            }
+            QosPolicy::Latest => {
+                let (tx, rx) = watch::channel(None);
+                (ClientChannel::Latest(tx), ClientReceiver::Latest(rx))
+            }
+        };
+
+        let disconnect = Arc::new(Notify::new());
+        let entry = ClientEntry {
+            channel,
+            qos,
+            encoding,
+            sent: 0,
+            dropped: 0,
+            consecutive_failures: 0,
+            last_success: Instant::now(),
+            disconnect: disconnect.clone(),
+        };
+
+        let mut clients = self.clients.lock().await;
+        clients.insert(client_id, entry);
+        println!(
+            "[{}] Added client {} (qos: {:?}, encoding: {:?}, total: {})",
+            self.name,
+            client_id,
+            qos,
+            encoding,
+            clients.len()
+        );
+
+        (receiver, disconnect)
+    }
+
+    /// Drop the client's broadcaster-side state and wake its connection task
+    /// (which is blocked reading from the client socket, not from this
+    /// channel) so it force-disconnects rather than leaking.
+    pub async fn remove_client(&self, client_id: ClientId) {
+        let mut clients = self.clients.lock().await;
+        let entry = clients.remove(&client_id);
+        drop(clients);
+        self.subjects.lock().await.remove_client(client_id);
+        if let Some(entry) = entry {
+            entry.disconnect.notify_one();
+            println!("[{}] Removed client {}", self.name, client_id);
+        }
+    }
+
+    /// Register interest in a subject (e.g. `trade_updates`, `quotes.AAPL`).
+    pub async fn subscribe(&self, client_id: ClientId, subject: &str) {
+        self.subjects.lock().await.subscribe(client_id, subject);
+        println!("[{}] Client {} subscribed to {}", self.name, client_id, subject);
+    }
+
+    pub async fn unsubscribe(&self, client_id: ClientId, subject: &str) {
+        self.subjects.lock().await.unsubscribe(client_id, subject);
+    }
+
+    /// Extract the subject from an incoming message and forward it only to
+    /// clients whose subscriptions match, per each client's QoS policy.
+    /// Messages we can't extract a subject from are dropped rather than
+    /// blindly broadcast, since there would be no way to know who actually
+    /// wants them.
+    pub async fn broadcast(&self, message: &str) {
+        let Some(subject) = extract_subject(message) else {
+            return;
+        };
+
+        let matched = self.subjects.lock().await.matches(&subject);
+        if matched.is_empty() {
+            return;
+        }
+
+        let text_frame = Frame::Text(Arc::from(message));
+        let mut msgpack_frame: Option<Frame> = None;
+
+        let mut to_evict = Vec::new();
+        {
+            let mut clients = self.clients.lock().await;
+            for client_id in &matched {
+                let Some(entry) = clients.get_mut(client_id) else {
+                    continue;
+                };
+
+                let frame = match entry.encoding {
+                    ClientEncoding::Json => text_frame.clone(),
+                    ClientEncoding::MsgPack => msgpack_frame
+                        .get_or_insert_with(|| self.encode_msgpack(message))
+                        .clone(),
+                };
+
+                if matches!(entry.send(frame), SendOutcome::Disconnected) {
+                    to_evict.push(*client_id);
+                } else if entry.is_stalled_reliable_client() {
+                    to_evict.push(*client_id);
+                }
+            }
+        }
+
+        for client_id in to_evict {
+            println!("[{}] Evicting client {}", self.name, client_id);
+            self.remove_client(client_id).await;
+        }
+    }
+
+    /// Transcode a JSON message into MessagePack, logging the achieved
+    /// compression ratio every `COMPRESSION_LOG_INTERVAL`th frame. Falls
+    /// back to the original JSON text on encode failure rather than
+    /// dropping the message, since every `MsgPack` client would otherwise
+    /// just silently miss it.
+    fn encode_msgpack(&self, message: &str) -> Frame {
+        let value: serde_json::Value = match serde_json::from_str(message) {
+            Ok(value) => value,
+            Err(_) => return Frame::Text(Arc::from(message)),
+        };
+
+        match rmp_serde::to_vec(&value) {
+            Ok(bytes) => {
+                let count = self.msgpack_frames_encoded.fetch_add(1, Ordering::Relaxed) + 1;
+                if count % COMPRESSION_LOG_INTERVAL == 1 {
+                    println!(
+                        "[{}] MessagePack compression: {} bytes -> {} bytes ({:.0}% of original)",
+                        self.name,
+                        message.len(),
+                        bytes.len(),
+                        (bytes.len() as f64 / message.len().max(1) as f64) * 100.0
+                    );
+                }
+                Frame::Binary(Arc::from(bytes))
+            }
+            Err(e) => {
+                eprintln!("[{}] Failed to MessagePack-encode message: {}", self.name, e);
+                Frame::Text(Arc::from(message))
+            }
+        }
+    }
+
+    async fn evict_stalled_clients(&self) {
+        let stalled: Vec<ClientId> = {
+            let clients = self.clients.lock().await;
+            clients
+                .iter()
+                .filter(|(_, entry)| entry.is_stalled_reliable_client())
+                .map(|(client_id, _)| *client_id)
+                .collect()
+        };
+
+        for client_id in stalled {
+            eprintln!(
+                "[{}] Evicting client {}: reliable channel stalled past {:?}",
+                self.name, client_id, RELIABLE_STALENESS_DEADLINE
+            );
+            self.remove_client(client_id).await;
+        }
+    }
+}
+
+/// Pull the routable subject out of an upstream Alpaca frame: trade-update
+/// and authorization frames alike carry it in the top-level `stream` field.
+fn extract_subject(message: &str) -> Option<String> {
+    let value: serde_json::Value = serde_json::from_str(message).ok()?;
+    value.get("stream")?.as_str().map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exact_subject_match() {
+        let mut trie = SubjectTrie::default();
+        let client = Uuid::new_v4();
+        trie.subscribe(client, "trade_updates");
+
+        assert_eq!(trie.matches("trade_updates"), HashSet::from([client]));
+        assert!(trie.matches("quotes.AAPL").is_empty());
+    }
+
+    #[test]
+    fn test_single_token_wildcard() {
+        let mut trie = SubjectTrie::default();
+        let client = Uuid::new_v4();
+        trie.subscribe(client, "quotes.*");
+
+        assert_eq!(trie.matches("quotes.AAPL"), HashSet::from([client]));
+        assert!(trie.matches("quotes.AAPL.extra").is_empty());
+        assert!(trie.matches("trades.AAPL").is_empty());
+    }
+
+    #[test]
+    fn test_multi_token_tail_wildcard() {
+        let mut trie = SubjectTrie::default();
+        let client = Uuid::new_v4();
+        trie.subscribe(client, "bars.>");
+
+        assert_eq!(trie.matches("bars.AAPL"), HashSet::from([client]));
+        assert_eq!(trie.matches("bars.AAPL.1Min"), HashSet::from([client]));
+    }
+
+    #[test]
+    fn test_remove_client_drops_all_its_subscriptions() {
+        let mut trie = SubjectTrie::default();
+        let client = Uuid::new_v4();
+        trie.subscribe(client, "trade_updates");
+        trie.subscribe(client, "quotes.*");
+
+        trie.remove_client(client);
+
+        assert!(trie.matches("trade_updates").is_empty());
+        assert!(trie.matches("quotes.AAPL").is_empty());
+    }
+
+    #[test]
+    fn test_extract_subject() {
+        assert_eq!(
+            extract_subject(r#"{"stream":"trade_updates","data":{}}"#),
+            Some("trade_updates".to_string())
+        );
+        assert_eq!(extract_subject("not json"), None);
+    }
+
+    #[test]
+    fn test_qos_policy_parse() {
+        assert_eq!(QosPolicy::parse(Some("latest")), QosPolicy::Latest);
+        assert_eq!(QosPolicy::parse(Some("RELIABLE")), QosPolicy::Reliable);
+        assert_eq!(QosPolicy::parse(Some("bogus")), QosPolicy::Drop);
+        assert_eq!(QosPolicy::parse(None), QosPolicy::Drop);
+    }
+
+    #[test]
+    fn test_client_encoding_parse() {
+        assert_eq!(ClientEncoding::parse(Some("msgpack")), ClientEncoding::MsgPack);
+        assert_eq!(ClientEncoding::parse(Some("MSGPACK")), ClientEncoding::MsgPack);
+        assert_eq!(ClientEncoding::parse(Some("bogus")), ClientEncoding::Json);
+        assert_eq!(ClientEncoding::parse(None), ClientEncoding::Json);
+    }
+}
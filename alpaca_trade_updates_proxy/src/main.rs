@@ -1,8 +1,8 @@
-use std::collections::HashMap;
+use std::collections::HashSet;
 use std::env;
 use std::sync::Arc;
 use tokio::net::TcpListener;
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, Notify};
 use tokio::time::{sleep, Duration};
 use tokio_tungstenite::{accept_hdr_async, connect_async, tungstenite::Message, WebSocketStream};
 use tokio_tungstenite::tungstenite::handshake::server::{Request, Response};
@@ -12,8 +12,21 @@ use uuid::Uuid;
 use tokio::net::TcpStream;
 use tokio_tungstenite::MaybeTlsStream;
 
-type ClientId = Uuid;
-type ClientSender = tokio::sync::mpsc::UnboundedSender<String>;
+mod auth;
+mod broadcaster;
+mod shutdown;
+
+use auth::{CidrAllowlist, CredentialStore};
+use broadcaster::{Broadcaster, ClientEncoding, ClientId, ClientReceiver, Frame, QosPolicy};
+use tokio::sync::watch;
+
+/// Pull a single `key=value` pair out of a raw (undecoded) query string.
+fn query_param<'a>(query: &'a str, key: &str) -> Option<&'a str> {
+    query.split('&').find_map(|pair| {
+        let (k, v) = pair.split_once('=')?;
+        (k == key).then_some(v)
+    })
+}
 
 #[derive(Debug, Clone)]
 enum FeedType {
@@ -80,25 +93,37 @@ struct UpstreamConnection {
     feed_type: FeedType,
     api_key: String,
     secret_key: String,
-    clients: Arc<Mutex<HashMap<ClientId, ClientSender>>>,
+    broadcaster: Arc<Broadcaster>,
+    /// Streams to `listen` for on every (re)connect, kept as explicit state
+    /// - like the firehose proxy's `active_subscriptions` - and replayed in
+    /// full after a dropped socket. Seeded with `trade_updates`; nothing
+    /// adds to or removes from it afterwards.
+    active_subscriptions: Mutex<HashSet<String>>,
 }
 
 impl UpstreamConnection {
     fn new(feed_type: FeedType, api_key: String, secret_key: String) -> Self {
+        let broadcaster = Broadcaster::new(feed_type.name());
         Self {
             feed_type,
             api_key,
             secret_key,
-            clients: Arc::new(Mutex::new(HashMap::new())),
+            broadcaster,
+            active_subscriptions: Mutex::new(HashSet::from(["trade_updates".to_string()])),
         }
     }
 
-    async fn run(&self) {
+    async fn run(&self, mut shutdown_rx: watch::Receiver<bool>) {
         let mut backoff = 1;
         loop {
+            if *shutdown_rx.borrow() {
+                println!("[{}] Shutdown in progress, not connecting to Alpaca", self.feed_type.name());
+                break;
+            }
+
             println!("[{}] Connecting to Alpaca...", self.feed_type.name());
 
-            match self.connect_and_stream().await {
+            match self.connect_and_stream(&mut shutdown_rx).await {
                 Ok(_) => {
                     println!("[{}] Connection closed normally", self.feed_type.name());
                     backoff = 1;
@@ -106,14 +131,28 @@ impl UpstreamConnection {
                 Err(e) => {
                     eprintln!("[{}] Error: {}. Reconnecting in {}s...",
                              self.feed_type.name(), e, backoff);
-                    sleep(Duration::from_secs(backoff)).await;
-                    backoff = (backoff * 2).min(60);
                 }
             }
+
+            if *shutdown_rx.borrow() {
+                break;
+            }
+
+            tokio::select! {
+                _ = sleep(Duration::from_secs(backoff)) => {}
+                _ = shutdown_rx.changed() => {
+                    println!("[{}] Shutdown received while waiting to reconnect to Alpaca", self.feed_type.name());
+                    break;
+                }
+            }
+            backoff = (backoff * 2).min(60);
         }
     }
 
-    async fn connect_and_stream(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    async fn connect_and_stream(
+        &self,
+        shutdown_rx: &mut watch::Receiver<bool>,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         let (ws_stream, _) = connect_async(self.feed_type.ws_url()).await?;
         let (mut write, mut read) = ws_stream.split();
 
@@ -141,11 +180,15 @@ impl UpstreamConnection {
                     if data.get("status") == Some(&json!("authorized")) {
                         println!("[{}] Authenticated successfully", self.feed_type.name());
 
-                        // Subscribe to trade_updates stream
+                        // Replay every stream in `active_subscriptions`, so a
+                        // reconnect listens for the same streams as before the
+                        // drop.
+                        let streams: Vec<String> =
+                            self.active_subscriptions.lock().await.iter().cloned().collect();
                         let listen_msg = json!({
                             "action": "listen",
                             "data": {
-                                "streams": ["trade_updates"]
+                                "streams": streams
                             }
                         });
                         write.send(Message::Text(listen_msg.to_string())).await?;
@@ -195,36 +238,47 @@ impl UpstreamConnection {
         });
 
         // Stream messages to all clients
-        while let Some(msg) = read.next().await {
-            match msg {
-                Ok(Message::Text(text)) => {
-                    self.broadcast_to_clients(&text).await;
-                }
-                Ok(Message::Binary(bytes)) => {
-                    let text = String::from_utf8_lossy(&bytes).to_string();
-                    self.broadcast_to_clients(&text).await;
-                }
-                Ok(Message::Close(_)) => {
-                    println!("[{}] Received close message", feed_name);
-                    break;
+        loop {
+            tokio::select! {
+                msg = read.next() => {
+                    let Some(msg) = msg else { break };
+                    match msg {
+                        Ok(Message::Text(text)) => {
+                            self.broadcast_to_clients(&text).await;
+                        }
+                        Ok(Message::Binary(bytes)) => {
+                            let text = String::from_utf8_lossy(&bytes).to_string();
+                            self.broadcast_to_clients(&text).await;
+                        }
+                        Ok(Message::Close(_)) => {
+                            println!("[{}] Received close message", feed_name);
+                            break;
+                        }
+                        Ok(Message::Ping(data)) => {
+                            let mut w = write.lock().await;
+                            let _ = w.send(Message::Pong(data)).await;
+                        }
+                        Ok(Message::Pong(data)) => {
+                            let ping_id = if data.len() >= 4 {
+                                u32::from_be_bytes([data[0], data[1], data[2], data[3]])
+                            } else {
+                                0
+                            };
+                            println!("[{}] Pong #{} received - connection alive", feed_name, ping_id);
+                        }
+                        Ok(_) => {}
+                        Err(e) => {
+                            eprintln!("[{}] WebSocket error: {}", feed_name, e);
+                            ping_task.abort();
+                            return Err(e.into());
+                        }
+                    }
                 }
-                Ok(Message::Ping(data)) => {
+                _ = shutdown_rx.changed() => {
+                    println!("[{}] Shutdown received, closing Alpaca connection", feed_name);
                     let mut w = write.lock().await;
-                    let _ = w.send(Message::Pong(data)).await;
-                }
-                Ok(Message::Pong(data)) => {
-                    let ping_id = if data.len() >= 4 {
-                        u32::from_be_bytes([data[0], data[1], data[2], data[3]])
-                    } else {
-                        0
-                    };
-                    println!("[{}] Pong #{} received - connection alive", feed_name, ping_id);
-                }
-                Ok(_) => {}
-                Err(e) => {
-                    eprintln!("[{}] WebSocket error: {}", feed_name, e);
-                    ping_task.abort();
-                    return Err(e.into());
+                    let _ = w.send(Message::Close(None)).await;
+                    break;
                 }
             }
         }
@@ -234,49 +288,25 @@ impl UpstreamConnection {
     }
 
     async fn broadcast_to_clients(&self, message: &str) {
-        let clients = self.clients.lock().await;
-        let client_count = clients.len();
-
-        // Log the message being broadcast (truncate for readability)
-        let preview = if message.len() > 200 {
-            format!("{}...", &message[..200])
-        } else {
-            message.to_string()
-        };
-        println!("[{}] Broadcasting to {} clients: {}", self.feed_type.name(), client_count, preview);
-
-        let mut disconnected = Vec::new();
-
-        for (client_id, sender) in clients.iter() {
-            if sender.send(message.to_string()).is_err() {
-                disconnected.push(*client_id);
-            }
-        }
-
-        drop(clients);
-
-        // Clean up disconnected clients
-        if !disconnected.is_empty() {
-            let mut clients = self.clients.lock().await;
-            for client_id in disconnected {
-                clients.remove(&client_id);
-                println!("[{}] Removed disconnected client {}", self.feed_type.name(), client_id);
-            }
-        }
+        self.broadcaster.broadcast(message).await;
     }
 
-    async fn add_client(&self, client_id: ClientId, sender: ClientSender) {
-        let mut clients = self.clients.lock().await;
-        clients.insert(client_id, sender);
-        println!("[{}] Added client {} (total: {})",
-                 self.feed_type.name(), client_id, clients.len());
+    async fn add_client(
+        &self,
+        client_id: ClientId,
+        qos: QosPolicy,
+        encoding: ClientEncoding,
+    ) -> (ClientReceiver, Arc<Notify>) {
+        let (receiver, disconnect) = self.broadcaster.add_client(client_id, qos, encoding).await;
+        // Every client currently wants the full trade_updates feed; once
+        // clients can request narrower subjects this should come from their
+        // auth/subscribe message instead.
+        self.broadcaster.subscribe(client_id, "trade_updates").await;
+        (receiver, disconnect)
     }
 
     async fn remove_client(&self, client_id: ClientId) {
-        let mut clients = self.clients.lock().await;
-        clients.remove(&client_id);
-        println!("[{}] Removed client {} (total: {})",
-                 self.feed_type.name(), client_id, clients.len());
+        self.broadcaster.remove_client(client_id).await;
     }
 }
 
@@ -300,6 +330,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     }
     println!();
 
+    let shutdown_rx = shutdown::listen();
+
     // Start upstream connection for paper trading
     let paper_upstream = Arc::new(UpstreamConnection::new(
         FeedType::Paper,
@@ -307,8 +339,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         config.alpaca_secret_key.clone(),
     ));
     let paper_upstream_clone = paper_upstream.clone();
+    let paper_shutdown_rx = shutdown_rx.clone();
     tokio::spawn(async move {
-        paper_upstream_clone.run().await;
+        paper_upstream_clone.run(paper_shutdown_rx).await;
     });
 
     // Start upstream connection for live trading (if credentials provided)
@@ -320,31 +353,81 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             live_secret,
         ));
         let upstream_clone = upstream.clone();
+        let live_shutdown_rx = shutdown_rx.clone();
         tokio::spawn(async move {
-            upstream_clone.run().await;
+            upstream_clone.run(live_shutdown_rx).await;
         });
         Some(upstream)
     } else {
         None
     };
 
+    let credential_store = Arc::new(CredentialStore::from_env());
+    let cidr_allowlist = CidrAllowlist::from_env();
+
     // Start TCP listener
     let listener = TcpListener::bind(format!("0.0.0.0:{}", config.proxy_port)).await?;
     println!("Proxy started successfully!\n");
 
-    while let Ok((stream, addr)) = listener.accept().await {
+    let mut accept_shutdown_rx = shutdown_rx.clone();
+    loop {
+        let (stream, addr) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(v) => v,
+                Err(e) => {
+                    eprintln!("Failed to accept connection: {}", e);
+                    continue;
+                }
+            },
+            _ = accept_shutdown_rx.changed() => {
+                println!("Shutdown received, no longer accepting new connections");
+                break;
+            }
+        };
+
+        if let Some(allowlist) = &cidr_allowlist {
+            if !allowlist.allows(addr.ip()) {
+                eprintln!("Rejecting connection from {}: not in allowed CIDR list", addr);
+                continue;
+            }
+        }
+
         println!("New connection from {}", addr);
 
         let paper = paper_upstream.clone();
         let live = live_upstream.clone();
+        let credential_store = credential_store.clone();
+        let conn_shutdown_rx = shutdown_rx.clone();
 
         tokio::spawn(async move {
             let path = Arc::new(Mutex::new(String::new()));
             let path_clone = path.clone();
+            let deflate_offered = Arc::new(Mutex::new(false));
+            let deflate_offered_clone = deflate_offered.clone();
 
             let callback = move |req: &Request, response: Response| {
                 let mut p = path_clone.blocking_lock();
-                *p = req.uri().path().to_string();
+                *p = req
+                    .uri()
+                    .path_and_query()
+                    .map(|pq| pq.as_str().to_string())
+                    .unwrap_or_else(|| req.uri().path().to_string());
+                drop(p);
+
+                // permessage-deflate isn't actually applied to outgoing frames
+                // below - tungstenite has no built-in support for the
+                // extension - so we deliberately don't echo it back in the
+                // response. We still record whether it was offered so
+                // operators can see how many clients would benefit if/when
+                // that support lands.
+                if let Some(value) = req.headers().get("Sec-WebSocket-Extensions") {
+                    if let Ok(value) = value.to_str() {
+                        if value.contains("permessage-deflate") {
+                            *deflate_offered_clone.blocking_lock() = true;
+                        }
+                    }
+                }
+
                 Ok(response)
             };
 
@@ -356,11 +439,35 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 }
             };
 
-            let request_path = path.lock().await.clone();
+            let request_target = path.lock().await.clone();
+            let (request_path, request_query) = match request_target.split_once('?') {
+                Some((path, query)) => (path.to_string(), Some(query.to_string())),
+                None => (request_target, None),
+            };
             let client_id = Uuid::new_v4();
+            let mut qos = QosPolicy::parse(
+                request_query
+                    .as_deref()
+                    .and_then(|query| query_param(query, "qos")),
+            );
+
+            if *deflate_offered.lock().await {
+                println!(
+                    "Client {} offered permessage-deflate (not yet honored, continuing uncompressed)",
+                    client_id
+                );
+            }
+
+            // A trailing `/msgpack` segment opts into binary MessagePack
+            // framing instead of JSON text, e.g. `/trade-updates-paper/msgpack`.
+            let (feed_path, path_encoding) = match request_path.strip_suffix("/msgpack") {
+                Some(stripped) => (stripped.to_string(), Some("msgpack")),
+                None => (request_path.clone(), None),
+            };
+            let mut encoding = ClientEncoding::parse(path_encoding);
 
             // Determine feed type from path
-            let (feed_type, upstream) = match FeedType::from_path(&request_path) {
+            let (feed_type, upstream) = match FeedType::from_path(&feed_path) {
                 Some(FeedType::Paper) => {
                     println!("[PAPER] New client connection: {}", client_id);
                     (FeedType::Paper, paper)
@@ -381,61 +488,136 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             };
 
             let (mut client_write, mut client_read) = ws_stream.split();
-            let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
-
-            // Wait for auth message from client
-            if let Some(msg) = client_read.next().await {
-                match msg {
-                    Ok(Message::Text(text)) => {
-                        if let Ok(value) = serde_json::from_str::<Value>(&text) {
-                            if value.get("action") == Some(&json!("auth")) {
-                                // Send success response (we accept any credentials)
-                                let success = json!([{"T": "success", "msg": "authenticated"}]);
-                                if let Err(e) = client_write.send(Message::Text(success.to_string())).await {
-                                    eprintln!("[{}] Failed to send auth response: {}", feed_type.name(), e);
-                                    return;
-                                }
-                                println!("[{}] Client {} authenticated", feed_type.name(), client_id);
-                            }
-                        }
+
+            // Wait for the client's auth message and validate its key/secret
+            // against the configured credential store rather than accepting
+            // any action:auth message.
+            let acl = match client_read.next().await {
+                Some(Ok(Message::Text(text))) => {
+                    let Ok(value) = serde_json::from_str::<Value>(&text) else {
+                        eprintln!("[{}] Client {} sent unparseable auth message", feed_type.name(), client_id);
+                        return;
+                    };
+
+                    if value.get("action") != Some(&json!("auth")) {
+                        eprintln!("[{}] Client {} did not open with an auth message", feed_type.name(), client_id);
+                        return;
+                    }
+
+                    // An auth-message `qos` field overrides the query parameter.
+                    if let Some(requested) = value.get("qos").and_then(|v| v.as_str()) {
+                        qos = QosPolicy::parse(Some(requested));
+                    }
+
+                    // An auth-message `encoding` field overrides the `/msgpack`
+                    // path suffix, mirroring how `qos` is overridden above.
+                    if let Some(requested) = value.get("encoding").and_then(|v| v.as_str()) {
+                        encoding = ClientEncoding::parse(Some(requested));
+                    }
+
+                    let key = value.get("key").and_then(|v| v.as_str()).unwrap_or_default();
+                    let secret = value.get("secret").and_then(|v| v.as_str()).unwrap_or_default();
+
+                    let Some(acl) = credential_store.authenticate(key, secret) else {
+                        eprintln!("[{}] Client {} failed authentication", feed_type.name(), client_id);
+                        let error = json!([{"T": "error", "code": 401, "msg": "authentication failed"}]);
+                        let _ = client_write.send(Message::Text(error.to_string())).await;
+                        return;
+                    };
+
+                    if !acl.allows_feed(&feed_type) {
+                        eprintln!(
+                            "[{}] Client {} denied: ACL does not permit this feed",
+                            feed_type.name(), client_id
+                        );
+                        let error = json!([{"T": "error", "code": 403, "msg": "feed not permitted"}]);
+                        let _ = client_write.send(Message::Text(error.to_string())).await;
+                        return;
                     }
-                    _ => {
-                        eprintln!("[{}] Client {} sent invalid auth message", feed_type.name(), client_id);
+
+                    let success = json!([{"T": "success", "msg": "authenticated"}]);
+                    if let Err(e) = client_write.send(Message::Text(success.to_string())).await {
+                        eprintln!("[{}] Failed to send auth response: {}", feed_type.name(), e);
                         return;
                     }
+                    println!("[{}] Client {} authenticated", feed_type.name(), client_id);
+                    acl
+                }
+                _ => {
+                    eprintln!("[{}] Client {} sent invalid auth message", feed_type.name(), client_id);
+                    return;
                 }
+            };
+
+            if !acl.allows_subject("trade_updates") {
+                eprintln!(
+                    "[{}] Client {} denied: ACL does not permit the trade_updates subject",
+                    feed_type.name(), client_id
+                );
+                let error = json!([{"T": "error", "code": 403, "msg": "subject not permitted"}]);
+                let _ = client_write.send(Message::Text(error.to_string())).await;
+                return;
             }
 
             // Add client to upstream's client list
-            upstream.add_client(client_id, tx).await;
+            let (mut rx, disconnect) = upstream.add_client(client_id, qos, encoding).await;
 
             // Spawn task to receive messages from upstream and send to client
             let client_id_clone = client_id;
             let feed_name = feed_type.name().to_string();
+            let mut relay_shutdown_rx = conn_shutdown_rx.clone();
             tokio::spawn(async move {
-                while let Some(message) = rx.recv().await {
-                    if let Err(e) = client_write.send(Message::Text(message)).await {
-                        eprintln!("[{}] Failed to send to client {}: {}", feed_name, client_id_clone, e);
-                        break;
+                loop {
+                    tokio::select! {
+                        frame = rx.recv() => {
+                            let Some(frame) = frame else { break };
+                            let ws_message = match frame {
+                                Frame::Text(text) => Message::Text(text.to_string()),
+                                Frame::Binary(bytes) => Message::Binary(bytes.to_vec()),
+                            };
+                            if let Err(e) = client_write.send(ws_message).await {
+                                eprintln!("[{}] Failed to send to client {}: {}", feed_name, client_id_clone, e);
+                                break;
+                            }
+                        }
+                        _ = relay_shutdown_rx.changed() => {
+                            println!("[{}] Shutdown: closing client {}", feed_name, client_id_clone);
+                            let _ = client_write.send(Message::Close(None)).await;
+                            break;
+                        }
                     }
                 }
             });
 
             // Listen for client messages (mostly just keep-alive)
-            while let Some(msg) = client_read.next().await {
-                match msg {
-                    Ok(Message::Close(_)) => {
-                        println!("[{}] Client {} closed connection", feed_type.name(), client_id);
-                        break;
+            let mut read_shutdown_rx = conn_shutdown_rx.clone();
+            loop {
+                tokio::select! {
+                    msg = client_read.next() => {
+                        let Some(msg) = msg else { break };
+                        match msg {
+                            Ok(Message::Close(_)) => {
+                                println!("[{}] Client {} closed connection", feed_type.name(), client_id);
+                                break;
+                            }
+                            Ok(Message::Ping(_data)) => {
+                                // Echo is handled automatically by tungstenite
+                            }
+                            Err(e) => {
+                                eprintln!("[{}] Client {} error: {}", feed_type.name(), client_id, e);
+                                break;
+                            }
+                            _ => {}
+                        }
                     }
-                    Ok(Message::Ping(_data)) => {
-                        // Echo is handled automatically by tungstenite
+                    _ = read_shutdown_rx.changed() => {
+                        println!("[{}] Shutdown: disconnecting client {}", feed_type.name(), client_id);
+                        break;
                     }
-                    Err(e) => {
-                        eprintln!("[{}] Client {} error: {}", feed_type.name(), client_id, e);
+                    _ = disconnect.notified() => {
+                        println!("[{}] Client {} evicted as a stalled consumer, force-disconnecting", feed_type.name(), client_id);
                         break;
                     }
-                    _ => {}
                 }
             }
 
@@ -444,5 +626,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         });
     }
 
+    println!("Draining in-flight connections...");
+    sleep(Duration::from_secs(2)).await;
+    println!("Shutdown complete");
+
     Ok(())
 }
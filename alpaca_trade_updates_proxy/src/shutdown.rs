@@ -0,0 +1,27 @@
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
+
+/// Returns a `watch` receiver that flips to `true` once SIGINT or SIGTERM is
+/// received. Every long-running loop in the proxy selects on this alongside
+/// its own work so a signal drains client and upstream connections cleanly
+/// instead of dropping sockets mid-flight.
+pub fn listen() -> watch::Receiver<bool> {
+    let (tx, rx) = watch::channel(false);
+
+    tokio::spawn(async move {
+        let mut sigterm =
+            signal(SignalKind::terminate()).expect("failed to install SIGTERM handler");
+        let mut sigint =
+            signal(SignalKind::interrupt()).expect("failed to install SIGINT handler");
+
+        tokio::select! {
+            _ = sigterm.recv() => println!("Received SIGTERM"),
+            _ = sigint.recv() => println!("Received SIGINT"),
+        }
+
+        println!("Shutting down gracefully...");
+        let _ = tx.send(true);
+    });
+
+    rx
+}
@@ -0,0 +1,181 @@
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::net::IpAddr;
+use tracing::warn;
+
+/// What an authenticated key is permitted to do: which subscription
+/// patterns it may request and which CIDR networks it may connect from. An
+/// empty `networks` list means "any address is allowed".
+#[derive(Debug, Clone)]
+struct AclEntry {
+    patterns: Vec<String>,
+    networks: Vec<(IpAddr, u8)>,
+}
+
+/// Maps API keys to their ACL grant, loaded from a colon-delimited file
+/// (`key:cidr1|cidr2:pattern1|pattern2`, one entry per line; an empty
+/// `cidrs` field means "any address", and `*`/`>` as a pattern means
+/// "every subscription"). Modeled on busrt's ACL maps so the proxy can be
+/// exposed beyond a trusted LAN instead of accepting any key
+/// unconditionally.
+pub struct AclTable {
+    entries: HashMap<String, AclEntry>,
+}
+
+impl AclTable {
+    pub fn load(path: &str) -> Result<Self> {
+        let contents =
+            fs::read_to_string(path).with_context(|| format!("failed to read ACL file {}", path))?;
+
+        let mut entries = HashMap::new();
+        for line in contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        {
+            let fields: Vec<&str> = line.splitn(3, ':').collect();
+            let [key, cidrs, patterns] = fields[..] else {
+                warn!("Skipping malformed ACL entry: {}", line);
+                continue;
+            };
+
+            let networks = cidrs
+                .split('|')
+                .map(str::trim)
+                .filter(|cidr| !cidr.is_empty())
+                .filter_map(parse_cidr)
+                .collect();
+
+            let patterns = patterns
+                .split('|')
+                .map(str::trim)
+                .filter(|pattern| !pattern.is_empty())
+                .map(str::to_string)
+                .collect();
+
+            entries.insert(key.to_string(), AclEntry { patterns, networks });
+        }
+
+        Ok(Self { entries })
+    }
+
+    /// Whether `key` is known and `addr` falls within its allowed networks
+    /// (or it has none configured, meaning any address is fine).
+    pub fn authorize_connection(&self, key: &str, addr: IpAddr) -> bool {
+        match self.entries.get(key) {
+            Some(entry) => {
+                entry.networks.is_empty()
+                    || entry
+                        .networks
+                        .iter()
+                        .any(|(network, prefix)| matches_cidr(addr, *network, *prefix))
+            }
+            None => false,
+        }
+    }
+
+    /// Whether `key`'s grant covers the requested subscription pattern -
+    /// exact match, or a wildcard grant broad enough to include it.
+    pub fn authorize_subscription(&self, key: &str, pattern: &str) -> bool {
+        let Some(entry) = self.entries.get(key) else {
+            return false;
+        };
+        entry
+            .patterns
+            .iter()
+            .any(|grant| grant == "*" || grant == ">" || grant == pattern || covers(grant, pattern))
+    }
+}
+
+fn parse_cidr(entry: &str) -> Option<(IpAddr, u8)> {
+    match entry.split_once('/') {
+        Some((addr, prefix)) => Some((addr.parse().ok()?, prefix.parse().ok()?)),
+        None => Some((entry.parse().ok()?, 32)),
+    }
+}
+
+fn matches_cidr(addr: IpAddr, network: IpAddr, prefix: u8) -> bool {
+    match (addr, network) {
+        (IpAddr::V4(addr), IpAddr::V4(network)) => {
+            let mask = if prefix == 0 {
+                0
+            } else {
+                u32::MAX << (32 - prefix.min(32))
+            };
+            u32::from(addr) & mask == u32::from(network) & mask
+        }
+        _ => addr == network,
+    }
+}
+
+/// NATS-style coverage check: does `grant` (e.g. `A.*`) cover `requested`
+/// (e.g. `A.AAPL`, or even a narrower wildcard like `A.*`)? Mirrors the
+/// subscription-matching semantics in `subscription_manager::matches`, but
+/// answers "is requested within grant" rather than "does this key match
+/// this pattern" - a literal grant token never covers a wildcard token in
+/// the same position, so a broader request can't sneak in under a
+/// narrower grant.
+fn covers(grant: &str, requested: &str) -> bool {
+    let grant_tokens: Vec<&str> = grant.split('.').collect();
+    let requested_tokens: Vec<&str> = requested.split('.').collect();
+
+    for (i, token) in grant_tokens.iter().enumerate() {
+        if *token == ">" {
+            return true;
+        }
+        match requested_tokens.get(i) {
+            Some(requested_token) if *token == "*" || token == requested_token => continue,
+            _ => return false,
+        }
+    }
+
+    grant_tokens.len() == requested_tokens.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_covers_exact_and_wildcard() {
+        assert!(covers("T.AAPL", "T.AAPL"));
+        assert!(covers("A.*", "A.AAPL"));
+        assert!(!covers("A.AAPL", "A.*"));
+        assert!(!covers("T.*", "Q.AAPL"));
+    }
+
+    #[test]
+    fn test_authorize_connection_respects_cidr_and_unknown_keys() {
+        let mut entries = HashMap::new();
+        entries.insert(
+            "good-key".to_string(),
+            AclEntry {
+                patterns: vec!["T.AAPL".to_string()],
+                networks: vec![("10.0.0.0".parse().unwrap(), 8)],
+            },
+        );
+        let table = AclTable { entries };
+
+        assert!(table.authorize_connection("good-key", "10.1.2.3".parse().unwrap()));
+        assert!(!table.authorize_connection("good-key", "11.0.0.1".parse().unwrap()));
+        assert!(!table.authorize_connection("unknown-key", "10.1.2.3".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_authorize_subscription_checks_grant() {
+        let mut entries = HashMap::new();
+        entries.insert(
+            "good-key".to_string(),
+            AclEntry {
+                patterns: vec!["A.*".to_string()],
+                networks: vec![],
+            },
+        );
+        let table = AclTable { entries };
+
+        assert!(table.authorize_subscription("good-key", "A.AAPL"));
+        assert!(!table.authorize_subscription("good-key", "T.AAPL"));
+        assert!(!table.authorize_subscription("unknown-key", "A.AAPL"));
+    }
+}
@@ -1,23 +1,43 @@
+use crate::acl::AclTable;
 use crate::subscription_manager::SubscriptionManager;
 use crate::types::{ClientId, ClientMessage, Cluster, StatusMessage};
 use anyhow::Result;
 use futures_util::{SinkExt, StreamExt};
 use std::collections::HashMap;
 use std::net::SocketAddr;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::{mpsc, Mutex};
+use tokio::sync::{mpsc, watch, Mutex, Notify};
+use tokio::time::{interval, Duration, Instant};
 use tokio_tungstenite::{accept_async, tungstenite::Message};
 use tracing::{debug, error, info, warn};
 use uuid::Uuid;
 
+// How often we send a server-initiated heartbeat ping to each client.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+
 pub struct ClientHandler {
     cluster: Cluster,
     port: u16,
     subscriptions: Arc<Mutex<SubscriptionManager>>,
-    clients: Arc<Mutex<HashMap<ClientId, mpsc::Sender<String>>>>,
+    clients: Arc<Mutex<HashMap<ClientId, mpsc::Sender<Arc<str>>>>>,
     firehose_tx: mpsc::Sender<String>,
     ms_agg_tx: mpsc::Sender<String>,
+    /// `Some` only when a `LiveDataSource` is running instead of the fake
+    /// generator; gets the same aggregate subscribe/unsubscribe traffic as
+    /// `firehose_tx`/`ms_agg_tx` so it can keep its upstream channels in
+    /// sync with live client demand.
+    live_tx: Option<mpsc::Sender<String>>,
+    /// `None` preserves the proxy's original trusted-LAN behavior: any key
+    /// from any address may subscribe to anything.
+    acl: Option<Arc<AclTable>>,
+    // Per-client channel depth, also used by SubscriptionManager as the
+    // item half of the slow-consumer queue cap.
+    max_queued_items: usize,
+    // How long a client may go without a heartbeat pong before it's
+    // considered dead.
+    client_idle_timeout: Duration,
 }
 
 impl ClientHandler {
@@ -27,6 +47,10 @@ impl ClientHandler {
         subscriptions: Arc<Mutex<SubscriptionManager>>,
         firehose_tx: mpsc::Sender<String>,
         ms_agg_tx: mpsc::Sender<String>,
+        live_tx: Option<mpsc::Sender<String>>,
+        acl: Option<Arc<AclTable>>,
+        max_queued_items: usize,
+        client_idle_timeout: Duration,
     ) -> Self {
         Self {
             cluster,
@@ -35,6 +59,10 @@ impl ClientHandler {
             clients: Arc::new(Mutex::new(HashMap::new())),
             firehose_tx,
             ms_agg_tx,
+            live_tx,
+            acl,
+            max_queued_items,
+            client_idle_timeout,
         }
     }
 
@@ -50,6 +78,21 @@ impl ClientHandler {
         Ok(())
     }
 
+    /// Push a direct reply (auth/subscribe/unsubscribe confirmation or
+    /// denial) into a client's own reply channel. Uses `try_send`, same as
+    /// `SubscriptionManager::dispatch`'s broadcast path, rather than a
+    /// blocking `send().await`: this channel is shared with that fan-out
+    /// path, so awaiting a full queue here while holding the global
+    /// `clients` lock would stall every other client's auth/subscribe
+    /// traffic too, defeating the point of per-client queue isolation.
+    async fn reply(&self, client_id: ClientId, payload: Arc<str>) {
+        if let Some(tx) = self.clients.lock().await.get(&client_id) {
+            if tx.try_send(payload).is_err() {
+                debug!("{} client {} reply channel full, dropping direct reply", self.cluster, client_id);
+            }
+        }
+    }
+
     async fn handle_client(self, stream: TcpStream, addr: SocketAddr) {
         let client_id = Uuid::new_v4();
         info!("{} client {} connected from {}", self.cluster, client_id, addr);
@@ -62,21 +105,90 @@ impl ClientHandler {
             }
         };
 
-        let (mut ws_tx, mut ws_rx) = ws_stream.split();
-        let (tx, mut rx) = mpsc::channel(100);
+        let (ws_tx, mut ws_rx) = ws_stream.split();
+        // Shared so both the forward task (heartbeat pings, routed payloads)
+        // and this task (replying to the client's own pings) can write to
+        // the socket without splitting it a second time.
+        let ws_tx = Arc::new(Mutex::new(ws_tx));
+        let (tx, mut rx) = mpsc::channel(self.max_queued_items);
 
-        // Register client
+        // Register client for direct replies (auth/subscribe confirmations)
         {
             let mut clients = self.clients.lock().await;
-            clients.insert(client_id, tx);
+            clients.insert(client_id, tx.clone());
         }
 
+        // Fired if the subscription manager evicts us as a slow consumer, so
+        // we don't just sit parked on `rx.recv()` forever.
+        let evicted = Arc::new(Notify::new());
+
+        // Updated whenever the client pongs one of our heartbeat pings, so
+        // the forward task can tell a genuinely dead socket from a merely
+        // quiet one.
+        let (pong_tx, mut pong_rx) = watch::channel(Instant::now());
+
+        // Fired by the forward task when it tears itself down (slow-consumer
+        // eviction or heartbeat idle timeout), so the read loop below also
+        // stops instead of sitting on `ws_rx.next()` forever - a `Close`
+        // frame sent on `ws_tx` doesn't make a stuck-but-TCP-alive client's
+        // read side return.
+        let disconnect_notify = Arc::new(Notify::new());
+
+        // Also register with the subscription manager, which owns the
+        // broadcast fan-out and will push filtered messages into `tx` itself.
+        // The returned counter tracks bytes `dispatch` has queued for us but
+        // we haven't drained yet, so we decrement it as we forward.
+        let queued_bytes = self
+            .subscriptions
+            .lock()
+            .await
+            .register_client(client_id, tx, evicted.clone());
+
         // Task to forward messages from router to client
         let clients_clone = self.clients.clone();
+        let evicted_for_forward = evicted.clone();
+        let ws_tx_for_forward = ws_tx.clone();
+        let client_idle_timeout = self.client_idle_timeout;
+        let disconnect_notify_for_forward = disconnect_notify.clone();
         let forward_task = tokio::spawn(async move {
-            while let Some(msg) = rx.recv().await {
-                if ws_tx.send(Message::Text(msg)).await.is_err() {
-                    break;
+            let mut heartbeat_interval = interval(HEARTBEAT_INTERVAL);
+
+            loop {
+                tokio::select! {
+                    msg = rx.recv() => {
+                        match msg {
+                            Some(msg) => {
+                                // Direct replies and status notices share this channel
+                                // without being counted by `dispatch`, so saturate at
+                                // zero instead of wrapping the counter.
+                                let _ = queued_bytes.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |b| {
+                                    Some(b.saturating_sub(msg.len()))
+                                });
+                                if ws_tx_for_forward.lock().await.send(Message::Text(msg.to_string())).await.is_err() {
+                                    break;
+                                }
+                            }
+                            None => break,
+                        }
+                    }
+                    _ = evicted_for_forward.notified() => {
+                        warn!("Client {} evicted as a slow consumer", client_id);
+                        let _ = ws_tx_for_forward.lock().await.send(Message::Close(None)).await;
+                        disconnect_notify_for_forward.notify_one();
+                        break;
+                    }
+                    _ = heartbeat_interval.tick() => {
+                        let idle_for = pong_rx.borrow().elapsed();
+                        if idle_for > client_idle_timeout {
+                            warn!("Client {} idle for {:?}, closing as dead", client_id, idle_for);
+                            let _ = ws_tx_for_forward.lock().await.send(Message::Close(None)).await;
+                            disconnect_notify_for_forward.notify_one();
+                            break;
+                        }
+                        if ws_tx_for_forward.lock().await.send(Message::Ping(vec![])).await.is_err() {
+                            break;
+                        }
+                    }
                 }
             }
             // Clean up on disconnect
@@ -85,45 +197,126 @@ impl ClientHandler {
         });
 
         // Handle messages from client
-        // Authentication is optional - proxy is local/trusted
+        // Authentication is optional when no ACL file is configured - proxy
+        // is local/trusted by default; once `self.acl` is set, a key that
+        // doesn't pass `authorize_connection` never gets `authenticated`.
         let mut authenticated = false;
+        let mut client_key: Option<String> = None;
 
-        while let Some(Ok(msg)) = ws_rx.next().await {
+        loop {
+            let msg = tokio::select! {
+                msg = ws_rx.next() => msg,
+                _ = disconnect_notify.notified() => {
+                    info!("{} client {} disconnect requested by forward task", self.cluster, client_id);
+                    break;
+                }
+            };
+            let Some(Ok(msg)) = msg else { break };
             match msg {
                 Message::Text(text) => {
                     match serde_json::from_str::<ClientMessage>(&text) {
-                        Ok(ClientMessage::Auth { params: _ }) => {
-                            // Optional auth - accept any key for proxy
+                        Ok(ClientMessage::Auth { params }) => {
+                            let forbidden = match &self.acl {
+                                Some(acl) => !acl.authorize_connection(&params, addr.ip()),
+                                None => false,
+                            };
+
+                            if forbidden {
+                                warn!(
+                                    "{} client {} denied: key not authorized for {}",
+                                    self.cluster, client_id, addr.ip()
+                                );
+                                let response = vec![StatusMessage {
+                                    status: "forbidden".to_string(),
+                                    message: "key not authorized for this address".to_string(),
+                                }];
+                                let response_text = serde_json::to_string(&response).unwrap();
+                                self.reply(client_id, Arc::from(response_text)).await;
+                                break;
+                            }
+
                             authenticated = true;
+                            client_key = Some(params);
                             let response = vec![StatusMessage {
                                 status: "auth_success".to_string(),
                                 message: "authenticated".to_string(),
                             }];
 
                             let response_text = serde_json::to_string(&response).unwrap();
-                            if let Some(tx) = self.clients.lock().await.get(&client_id) {
-                                let _ = tx.send(response_text).await;
-                            }
+                            self.reply(client_id, Arc::from(response_text)).await;
 
                             info!("{} client {} authenticated", self.cluster, client_id);
                         }
                         Ok(ClientMessage::Subscribe { params }) => {
-                            // Auto-authenticate on first subscribe if not already authenticated
+                            // With no ACL configured, auto-authenticate on first
+                            // subscribe as before. With one configured, a key is
+                            // required up front via an Auth message.
+                            if self.acl.is_some() && client_key.is_none() {
+                                warn!(
+                                    "{} client {} denied: subscribe before authenticating",
+                                    self.cluster, client_id
+                                );
+                                let response = vec![StatusMessage {
+                                    status: "forbidden".to_string(),
+                                    message: "authenticate before subscribing".to_string(),
+                                }];
+                                let response_text = serde_json::to_string(&response).unwrap();
+                                self.reply(client_id, Arc::from(response_text)).await;
+                                continue;
+                            }
+
                             if !authenticated {
                                 authenticated = true;
                                 info!("{} client {} auto-authenticated", self.cluster, client_id);
                             }
+
+                            // Filter the requested patterns down to ones this
+                            // key's grant actually covers; an unconfigured ACL
+                            // covers everything, matching the old behavior.
+                            let requested: Vec<&str> = params
+                                .split(',')
+                                .map(str::trim)
+                                .filter(|p| !p.is_empty())
+                                .collect();
+                            let (allowed, denied): (Vec<&str>, Vec<&str>) = match &self.acl {
+                                Some(acl) => {
+                                    let key = client_key.as_deref().unwrap_or_default();
+                                    requested
+                                        .into_iter()
+                                        .partition(|pattern| acl.authorize_subscription(key, pattern))
+                                }
+                                None => (requested, Vec::new()),
+                            };
+
+                            if !denied.is_empty() {
+                                warn!(
+                                    "{} client {} denied subscription to {}",
+                                    self.cluster, client_id, denied.join(",")
+                                );
+                                let response = vec![StatusMessage {
+                                    status: "forbidden".to_string(),
+                                    message: format!("not permitted: {}", denied.join(",")),
+                                }];
+                                let response_text = serde_json::to_string(&response).unwrap();
+                                self.reply(client_id, Arc::from(response_text)).await;
+                            }
+
+                            if allowed.is_empty() {
+                                continue;
+                            }
+                            let allowed_params = allowed.join(",");
+
                             // Update subscriptions
-                            {
+                            let rejected = {
                                 let mut subs = self.subscriptions.lock().await;
-                                subs.add_subscription(client_id, &params);
+                                let rejected = subs.add_subscription(client_id, &allowed_params);
 
                                 // Send updated subscriptions to BOTH upstreams
                                 // Firehose: non-bar data (T.*, Q.*, LULD.*, FMV.*)
                                 let firehose_sub = subs.get_firehose_subscription();
                                 if !firehose_sub.is_empty() {
                                     let sub_msg = serde_json::to_string(&ClientMessage::Subscribe {
-                                        params: firehose_sub,
+                                        params: firehose_sub.clone(),
                                     }).unwrap();
                                     let _ = self.firehose_tx.send(sub_msg).await;
                                 }
@@ -132,24 +325,66 @@ impl ClientHandler {
                                 let ms_agg_sub = subs.get_ms_aggregator_subscription();
                                 if !ms_agg_sub.is_empty() {
                                     let sub_msg = serde_json::to_string(&ClientMessage::Subscribe {
-                                        params: ms_agg_sub,
+                                        params: ms_agg_sub.clone(),
                                     }).unwrap();
                                     let _ = self.ms_agg_tx.send(sub_msg).await;
                                 }
+
+                                // Live data source: same aggregate set as the
+                                // two upstreams above, just on one channel.
+                                if let Some(live_tx) = &self.live_tx {
+                                    let live_sub: Vec<&str> = [firehose_sub.as_str(), ms_agg_sub.as_str()]
+                                        .into_iter()
+                                        .filter(|s| !s.is_empty())
+                                        .collect();
+                                    if !live_sub.is_empty() {
+                                        let sub_msg = serde_json::to_string(&ClientMessage::Subscribe {
+                                            params: live_sub.join(","),
+                                        }).unwrap();
+                                        let _ = live_tx.send(sub_msg).await;
+                                    }
+                                }
+
+                                rejected
+                            };
+
+                            if !rejected.is_empty() {
+                                warn!(
+                                    "{} client {} subscription rejected (at capacity): {}",
+                                    self.cluster, client_id, rejected.join(",")
+                                );
+                                let response = vec![StatusMessage {
+                                    status: "forbidden".to_string(),
+                                    message: format!("at capacity: {}", rejected.join(",")),
+                                }];
+                                let response_text = serde_json::to_string(&response).unwrap();
+                                self.reply(client_id, Arc::from(response_text)).await;
                             }
-                            
+
                             // Send confirmation
                             let response = vec![StatusMessage {
                                 status: "success".to_string(),
-                                message: format!("subscribed to {}", params),
+                                message: format!("subscribed to {}", allowed_params),
                             }];
-                            
+
                             let response_text = serde_json::to_string(&response).unwrap();
-                            if let Some(tx) = self.clients.lock().await.get(&client_id) {
-                                let _ = tx.send(response_text).await;
+                            self.reply(client_id, Arc::from(response_text)).await;
+
+                            // Bar channels (A.*, AM.*, *Ms.*) get the last
+                            // emitted bar per key pushed immediately, so the
+                            // client has a "current state" snapshot instead
+                            // of waiting up to a full window for the live
+                            // stream to catch up.
+                            let checkpoints = self
+                                .subscriptions
+                                .lock()
+                                .await
+                                .bar_checkpoints_for(&allowed_params);
+                            for payload in checkpoints {
+                                self.reply(client_id, payload).await;
                             }
-                            
-                            debug!("{} client {} subscribed to {}", self.cluster, client_id, params);
+
+                            debug!("{} client {} subscribed to {}", self.cluster, client_id, allowed_params);
                         }
                         Ok(ClientMessage::Unsubscribe { params }) => {
                             // Update subscriptions
@@ -185,6 +420,14 @@ impl ClientHandler {
                                         }).unwrap();
                                         let _ = self.ms_agg_tx.send(unsub_msg).await;
                                     }
+
+                                    // Send to live data source, if running
+                                    if let Some(live_tx) = &self.live_tx {
+                                        let unsub_msg = serde_json::to_string(&ClientMessage::Unsubscribe {
+                                            params: to_unsub.join(","),
+                                        }).unwrap();
+                                        let _ = live_tx.send(unsub_msg).await;
+                                    }
                                 }
                             }
 
@@ -195,9 +438,7 @@ impl ClientHandler {
                             }];
 
                             let response_text = serde_json::to_string(&response).unwrap();
-                            if let Some(tx) = self.clients.lock().await.get(&client_id) {
-                                let _ = tx.send(response_text).await;
-                            }
+                            self.reply(client_id, Arc::from(response_text)).await;
 
                             debug!("{} client {} unsubscribed from {}", self.cluster, client_id, params);
                         }
@@ -208,10 +449,10 @@ impl ClientHandler {
                 }
                 Message::Close(_) => break,
                 Message::Ping(data) => {
-                    if let Some(tx) = self.clients.lock().await.get(&client_id) {
-                        // Forward ping as text message containing pong
-                        let _ = tx.send(format!("pong:{:?}", data)).await;
-                    }
+                    let _ = ws_tx.lock().await.send(Message::Pong(data)).await;
+                }
+                Message::Pong(_) => {
+                    let _ = pong_tx.send(Instant::now());
                 }
                 _ => {}
             }
@@ -230,10 +471,6 @@ impl ClientHandler {
         
         info!("{} client {} disconnected", self.cluster, client_id);
     }
-
-    pub fn get_clients(&self) -> Arc<Mutex<HashMap<ClientId, mpsc::Sender<String>>>> {
-        self.clients.clone()
-    }
 }
 
 impl Clone for ClientHandler {
@@ -245,6 +482,10 @@ impl Clone for ClientHandler {
             clients: self.clients.clone(),
             firehose_tx: self.firehose_tx.clone(),
             ms_agg_tx: self.ms_agg_tx.clone(),
+            live_tx: self.live_tx.clone(),
+            acl: self.acl.clone(),
+            max_queued_items: self.max_queued_items,
+            client_idle_timeout: self.client_idle_timeout,
         }
     }
 }
\ No newline at end of file
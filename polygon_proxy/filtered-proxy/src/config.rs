@@ -8,6 +8,35 @@ pub struct Config {
     pub polygon_api_key: String,
     pub stocks_port: u16,
     pub log_level: String,
+    /// Consecutive full-channel drops before a client is evicted as a slow consumer
+    pub slow_client_drop_threshold: u32,
+    /// Path to a `key:cidrs:patterns` ACL file (see `acl::AclTable`). When
+    /// unset, every key is accepted from any address with no subscription
+    /// restrictions, preserving the proxy's original trusted-LAN behavior.
+    pub acl_file: Option<String>,
+    /// Per-client channel depth, also used as the item half of the
+    /// slow-consumer queue cap.
+    pub max_queued_items: usize,
+    /// Per-client outstanding-bytes cap; a client parked past this (or past
+    /// `max_queued_items`) for longer than `slow_consumer_grace_secs` is
+    /// evicted as a slow consumer.
+    pub max_queued_bytes: usize,
+    /// How long a client may sit over its queue limits before eviction, so
+    /// a brief burst doesn't trip it.
+    pub slow_consumer_grace_secs: u64,
+    /// Global cap on distinct active subscription keys/patterns; new
+    /// `Subscribe` requests past this are rejected rather than accepted
+    /// unboundedly.
+    pub max_active_subscriptions: usize,
+    /// How long a client may go without a pong to our heartbeat pings
+    /// before its connection is considered dead and torn down.
+    pub client_idle_timeout_ms: u64,
+    /// WebSocket URL for a live exchange feed (see `live_data_source`).
+    /// When set, it replaces the fake data generator as the proxy's third
+    /// data source alongside the firehose/ms-aggregator upstreams; when
+    /// unset, the fake generator runs instead so local dev needs no real
+    /// credentials.
+    pub live_data_source_url: Option<String>,
 }
 
 impl Config {
@@ -26,6 +55,26 @@ impl Config {
                 .parse()?,
             log_level: env::var("LOG_LEVEL")
                 .unwrap_or_else(|_| "info".to_string()),
+            slow_client_drop_threshold: env::var("SLOW_CLIENT_DROP_THRESHOLD")
+                .unwrap_or_else(|_| "50".to_string())
+                .parse()?,
+            acl_file: env::var("ACL_FILE").ok(),
+            max_queued_items: env::var("MAX_QUEUED_ITEMS")
+                .unwrap_or_else(|_| "100".to_string())
+                .parse()?,
+            max_queued_bytes: env::var("MAX_QUEUED_BYTES")
+                .unwrap_or_else(|_| "1048576".to_string())
+                .parse()?,
+            slow_consumer_grace_secs: env::var("SLOW_CONSUMER_GRACE_SECS")
+                .unwrap_or_else(|_| "10".to_string())
+                .parse()?,
+            max_active_subscriptions: env::var("MAX_ACTIVE_SUBSCRIPTIONS")
+                .unwrap_or_else(|_| "10000".to_string())
+                .parse()?,
+            client_idle_timeout_ms: env::var("CLIENT_IDLE_TIMEOUT_MS")
+                .unwrap_or_else(|_| "90000".to_string())
+                .parse()?,
+            live_data_source_url: env::var("LIVE_DATA_SOURCE_URL").ok(),
         })
     }
 }
\ No newline at end of file
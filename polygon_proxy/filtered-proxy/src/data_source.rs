@@ -0,0 +1,23 @@
+use crate::subscription_manager::SubscriptionManager;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::sync::{mpsc, Mutex};
+
+/// A pluggable source of firehose/ms-aggregator-shaped messages, fed into
+/// the same two channels the rest of the proxy routes through regardless
+/// of where the data actually comes from - so the downstream pipeline
+/// (`SubscriptionManager`, client fan-out) runs unchanged whether it's
+/// backed by synthetic data or a live upstream feed.
+///
+/// `run` never returns in practice (both implementations loop forever,
+/// reconnecting or regenerating as needed), but isn't `-> !` so a caller
+/// can still race it against other futures if it ever needs to.
+pub trait DataSource: Send + Sync {
+    fn run<'a>(
+        &'a self,
+        firehose_tx: mpsc::Sender<String>,
+        ms_agg_tx: mpsc::Sender<String>,
+        subscriptions: Arc<Mutex<SubscriptionManager>>,
+    ) -> Pin<Box<dyn Future<Output = ()> + Send + 'a>>;
+}
@@ -1,31 +1,31 @@
+use crate::data_source::DataSource;
 use crate::subscription_manager::SubscriptionManager;
 use serde_json::json;
+use std::future::Future;
+use std::pin::Pin;
 use std::sync::Arc;
 use std::time::{SystemTime, UNIX_EPOCH};
 use tokio::sync::{mpsc, Mutex};
 use tokio::time::{interval, Duration};
 use tracing::debug;
 
-pub struct FakeDataGenerator {
-    firehose_tx: mpsc::Sender<String>,
-    ms_agg_tx: mpsc::Sender<String>,
-    subscriptions: Arc<Mutex<SubscriptionManager>>,
-}
+/// Synthetic `DataSource` for local testing: random-walks a price for
+/// `FAKETICKER` and emits trades/quotes/bars for it, gated on whether
+/// anyone's actually subscribed so an idle proxy doesn't churn messages
+/// nobody receives.
+pub struct FakeDataGenerator;
 
 impl FakeDataGenerator {
-    pub fn new(
+    pub fn new() -> Self {
+        Self
+    }
+
+    async fn generate(
+        &self,
         firehose_tx: mpsc::Sender<String>,
         ms_agg_tx: mpsc::Sender<String>,
         subscriptions: Arc<Mutex<SubscriptionManager>>,
-    ) -> Self {
-        Self {
-            firehose_tx,
-            ms_agg_tx,
-            subscriptions,
-        }
-    }
-
-    pub async fn start(&self) {
+    ) {
         let mut tick_interval = interval(Duration::from_millis(100));
         let mut price = 100.0;
 
@@ -38,7 +38,7 @@ impl FakeDataGenerator {
             let change = (rand::random::<f64>() - 0.5) * 2.0;
             price = (price + change).max(50.0).min(150.0); // Keep in reasonable range
 
-            let subs = self.subscriptions.lock().await;
+            let subs = subscriptions.lock().await;
 
             // Only generate Trade if someone subscribed to T.FAKETICKER
             if subs.has_subscription("T.FAKETICKER") {
@@ -55,7 +55,7 @@ impl FakeDataGenerator {
                 }]);
 
                 if let Ok(msg_str) = serde_json::to_string(&trade_msg) {
-                    let _ = self.firehose_tx.send(msg_str).await;
+                    let _ = firehose_tx.send(msg_str).await;
                 }
             }
 
@@ -74,7 +74,7 @@ impl FakeDataGenerator {
                 }]);
 
                 if let Ok(msg_str) = serde_json::to_string(&quote_msg) {
-                    let _ = self.firehose_tx.send(msg_str).await;
+                    let _ = firehose_tx.send(msg_str).await;
                 }
             }
 
@@ -93,7 +93,7 @@ impl FakeDataGenerator {
                 }]);
 
                 if let Ok(msg_str) = serde_json::to_string(&bar_msg) {
-                    let _ = self.ms_agg_tx.send(msg_str).await;
+                    let _ = ms_agg_tx.send(msg_str).await;
                 }
             }
 
@@ -112,13 +112,24 @@ impl FakeDataGenerator {
                 }]);
 
                 if let Ok(msg_str) = serde_json::to_string(&min_bar_msg) {
-                    let _ = self.ms_agg_tx.send(msg_str).await;
+                    let _ = ms_agg_tx.send(msg_str).await;
                 }
             }
         }
     }
 }
 
+impl DataSource for FakeDataGenerator {
+    fn run<'a>(
+        &'a self,
+        firehose_tx: mpsc::Sender<String>,
+        ms_agg_tx: mpsc::Sender<String>,
+        subscriptions: Arc<Mutex<SubscriptionManager>>,
+    ) -> Pin<Box<dyn Future<Output = ()> + Send + 'a>> {
+        Box::pin(self.generate(firehose_tx, ms_agg_tx, subscriptions))
+    }
+}
+
 // Helper to get current time in milliseconds
 fn now_millis() -> u64 {
     SystemTime::now()
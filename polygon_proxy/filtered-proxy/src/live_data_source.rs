@@ -0,0 +1,213 @@
+use crate::data_source::DataSource;
+use crate::subscription_manager::SubscriptionManager;
+use crate::types::ClientMessage;
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::sync::{mpsc, Mutex};
+use tokio::time::{interval, Duration};
+use tokio_tungstenite::{connect_async, tungstenite::Message, MaybeTlsStream, WebSocketStream};
+use tracing::{debug, error, info, warn};
+
+type LiveWrite =
+    futures_util::stream::SplitSink<WebSocketStream<MaybeTlsStream<tokio::net::TcpStream>>, Message>;
+
+// Exponential backoff bounds for reconnect attempts, same shape as
+// `UpstreamConnection`'s.
+const RECONNECT_BASE_DELAY: Duration = Duration::from_secs(1);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(60);
+
+// Application-level keepalive cadence, matching the FTX/Bitz client pattern
+// of a 15s ping independent of the WebSocket protocol's own ping/pong.
+const APP_PING_INTERVAL: Duration = Duration::from_secs(15);
+
+/// `DataSource` backed by a real exchange WebSocket feed, reached via
+/// `tokio-tungstenite`. Subscriptions are translated from client demand
+/// (relayed over `rx_cmd`, the same aggregate-subscribe/unsubscribe
+/// traffic `ClientHandler` sends the firehose/ms-aggregator upstreams)
+/// into the upstream's `{"op":"subscribe","channel":...}` command shape,
+/// and the full active set is replayed after every reconnect so clients
+/// who subscribed before a drop keep getting data without resubscribing
+/// themselves.
+pub struct LiveDataSource {
+    url: String,
+    rx_cmd: Mutex<mpsc::Receiver<String>>,
+}
+
+impl LiveDataSource {
+    pub fn new(url: String, rx_cmd: mpsc::Receiver<String>) -> Self {
+        Self {
+            url,
+            rx_cmd: Mutex::new(rx_cmd),
+        }
+    }
+
+    async fn run_supervised(
+        &self,
+        firehose_tx: mpsc::Sender<String>,
+        ms_agg_tx: mpsc::Sender<String>,
+        subscriptions: Arc<Mutex<SubscriptionManager>>,
+    ) {
+        let mut backoff = RECONNECT_BASE_DELAY;
+
+        loop {
+            match self
+                .connect_and_forward(&firehose_tx, &ms_agg_tx, &subscriptions)
+                .await
+            {
+                Ok(()) => {
+                    // A clean close still means there's no feed anymore;
+                    // reconnect the same as on error.
+                }
+                Err(e) => {
+                    error!("live data source connection error: {}", e);
+                }
+            }
+
+            warn!("live data source reconnecting in {:?}", backoff);
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(RECONNECT_MAX_DELAY);
+        }
+    }
+
+    async fn connect_and_forward(
+        &self,
+        firehose_tx: &mpsc::Sender<String>,
+        ms_agg_tx: &mpsc::Sender<String>,
+        subscriptions: &Arc<Mutex<SubscriptionManager>>,
+    ) -> anyhow::Result<()> {
+        info!("live data source connecting to {}", self.url);
+
+        let (ws_stream, _) = connect_async(&self.url).await?;
+        let (mut write, mut read) = ws_stream.split();
+
+        info!("live data source connected");
+
+        replay_subscriptions(&mut write, subscriptions).await?;
+
+        let mut ping_interval = interval(APP_PING_INTERVAL);
+        let mut rx_cmd = self.rx_cmd.lock().await;
+
+        loop {
+            tokio::select! {
+                msg = read.next() => {
+                    let Some(msg) = msg else {
+                        warn!("live data source stream ended");
+                        break;
+                    };
+                    match msg? {
+                        Message::Text(text) => {
+                            route_normalized(&text, firehose_tx, ms_agg_tx).await;
+                        }
+                        Message::Close(_) => {
+                            warn!("live data source connection closed by upstream");
+                            break;
+                        }
+                        Message::Pong(_) => {
+                            debug!("live data source received pong");
+                        }
+                        Message::Ping(data) => {
+                            write.send(Message::Pong(data)).await?;
+                        }
+                        _ => {} // Ignore binary
+                    }
+                }
+
+                // New/changed client subscriptions, relayed by ClientHandler
+                // the same way they're relayed to the firehose/ms-aggregator
+                // upstreams.
+                Some(cmd) = rx_cmd.recv() => {
+                    if let Err(e) = forward_command(&mut write, &cmd).await {
+                        warn!("live data source failed to forward client command: {}", e);
+                        break;
+                    }
+                }
+
+                _ = ping_interval.tick() => {
+                    let ping = json!({"op": "ping"});
+                    if write.send(Message::Text(ping.to_string())).await.is_err() {
+                        warn!("live data source failed to send app-level ping");
+                        break;
+                    }
+                    debug!("live data source sent app-level ping");
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Normalized upstream messages are tagged by `channel` so they can be
+// routed to whichever of the two downstream queues matches - bars go to
+// the ms-aggregator path, everything else (trades, quotes) to firehose.
+async fn route_normalized(
+    text: &str,
+    firehose_tx: &mpsc::Sender<String>,
+    ms_agg_tx: &mpsc::Sender<String>,
+) {
+    let Ok(value) = serde_json::from_str::<Value>(text) else {
+        debug!("live data source dropped unparseable message: {}", text);
+        return;
+    };
+
+    let is_bar = matches!(value.get("channel").and_then(Value::as_str), Some(c) if c.starts_with('A'));
+
+    if is_bar {
+        let _ = ms_agg_tx.send(text.to_string()).await;
+    } else {
+        let _ = firehose_tx.send(text.to_string()).await;
+    }
+}
+
+// Translate one relayed `ClientMessage` (carrying a comma-separated
+// aggregate of subscription keys, same as what's sent upstream to
+// firehose/ms-aggregator) into individual op/channel commands.
+async fn forward_command(write: &mut LiveWrite, cmd: &str) -> anyhow::Result<()> {
+    match serde_json::from_str::<ClientMessage>(cmd)? {
+        ClientMessage::Subscribe { params } => send_per_channel(write, &params, "subscribe").await,
+        ClientMessage::Unsubscribe { params } => send_per_channel(write, &params, "unsubscribe").await,
+        _ => Ok(()),
+    }
+}
+
+async fn send_per_channel(write: &mut LiveWrite, params: &str, op: &str) -> anyhow::Result<()> {
+    for channel in params.split(',').map(str::trim).filter(|c| !c.is_empty()) {
+        let msg = json!({"op": op, "channel": channel});
+        write.send(Message::Text(msg.to_string())).await?;
+    }
+    Ok(())
+}
+
+async fn replay_subscriptions(
+    write: &mut LiveWrite,
+    subscriptions: &Arc<Mutex<SubscriptionManager>>,
+) -> anyhow::Result<()> {
+    let subs = subscriptions.lock().await;
+    let firehose_channels = subs.get_firehose_subscription();
+    let ms_agg_channels = subs.get_ms_aggregator_subscription();
+    drop(subs);
+
+    for channels in [firehose_channels, ms_agg_channels] {
+        if channels.is_empty() {
+            continue;
+        }
+        info!("live data source replaying subscriptions: {}", channels);
+        send_per_channel(write, &channels, "subscribe").await?;
+    }
+
+    Ok(())
+}
+
+impl DataSource for LiveDataSource {
+    fn run<'a>(
+        &'a self,
+        firehose_tx: mpsc::Sender<String>,
+        ms_agg_tx: mpsc::Sender<String>,
+        subscriptions: Arc<Mutex<SubscriptionManager>>,
+    ) -> Pin<Box<dyn Future<Output = ()> + Send + 'a>> {
+        Box::pin(self.run_supervised(firehose_tx, ms_agg_tx, subscriptions))
+    }
+}
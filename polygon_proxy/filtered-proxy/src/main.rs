@@ -1,22 +1,29 @@
+mod acl;
 mod client_handler;
 mod config;
+mod data_source;
 mod fake_data_generator;
+mod live_data_source;
 mod router;
 mod subscription_manager;
 mod types;
 mod upstream;
 
+use acl::AclTable;
 use anyhow::Result;
 use client_handler::ClientHandler;
 use config::Config;
+use data_source::DataSource;
 use fake_data_generator::FakeDataGenerator;
+use live_data_source::LiveDataSource;
 use router::Router;
 use std::sync::Arc;
 use subscription_manager::SubscriptionManager;
 use tokio::sync::{mpsc, Mutex};
+use tokio::time::Duration;
 use tracing::info;
 use types::Cluster;
-use upstream::UpstreamConnection;
+use upstream::{UpstreamConnection, UpstreamKind};
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -42,7 +49,13 @@ async fn start_cluster_proxy(cluster: Cluster, config: Config) -> Result<()> {
     info!("Ms-Aggregator URL: {}", config.ms_aggregator_url);
 
     // Create shared subscription manager
-    let subscriptions = Arc::new(Mutex::new(SubscriptionManager::new()));
+    let subscriptions = Arc::new(Mutex::new(SubscriptionManager::new(
+        config.slow_client_drop_threshold,
+        config.max_queued_items,
+        config.max_queued_bytes,
+        Duration::from_secs(config.slow_consumer_grace_secs),
+        config.max_active_subscriptions,
+    )));
 
     // Create channels for upstream communication
     // Firehose: for trades, quotes, etc. (non-bar data)
@@ -53,6 +66,21 @@ async fn start_cluster_proxy(cluster: Cluster, config: Config) -> Result<()> {
     let (ms_agg_tx, mut ms_agg_rx) = mpsc::channel(100);
     let (ms_agg_cmd_tx, ms_agg_cmd_rx) = mpsc::channel(100);
 
+    let acl = match &config.acl_file {
+        Some(path) => Some(Arc::new(AclTable::load(path)?)),
+        None => None,
+    };
+
+    // Live data source command channel: only wired up when a live feed is
+    // actually configured, so ClientHandler has nothing extra to forward
+    // to (and nothing extra to drop) when the fake generator is running.
+    let (live_cmd_tx, live_cmd_rx) = if config.live_data_source_url.is_some() {
+        let (tx, rx) = mpsc::channel(100);
+        (Some(tx), Some(rx))
+    } else {
+        (None, None)
+    };
+
     // Start client handler (with both upstream command channels)
     let client_handler = ClientHandler::new(
         cluster,
@@ -60,10 +88,12 @@ async fn start_cluster_proxy(cluster: Cluster, config: Config) -> Result<()> {
         subscriptions.clone(),
         firehose_cmd_tx.clone(),
         ms_agg_cmd_tx.clone(),
+        live_cmd_tx,
+        acl,
+        config.max_queued_items,
+        Duration::from_millis(config.client_idle_timeout_ms),
     );
 
-    let clients = client_handler.get_clients();
-
     tokio::spawn(async move {
         if let Err(e) = client_handler.run().await {
             tracing::error!("{} client handler error: {}", cluster, e);
@@ -73,10 +103,12 @@ async fn start_cluster_proxy(cluster: Cluster, config: Config) -> Result<()> {
     // Start upstream connection to firehose (non-bar data)
     let firehose_upstream = UpstreamConnection::new(
         cluster,
+        UpstreamKind::Firehose,
         config.firehose_url.clone(),
         config.polygon_api_key.clone(),
         firehose_tx.clone(),
         firehose_cmd_rx,
+        subscriptions.clone(),
     );
 
     tokio::spawn(async move {
@@ -86,31 +118,48 @@ async fn start_cluster_proxy(cluster: Cluster, config: Config) -> Result<()> {
     // Start upstream connection to ms-aggregator (bar data)
     let ms_agg_upstream = UpstreamConnection::new(
         cluster,
+        UpstreamKind::MsAggregator,
         config.ms_aggregator_url.clone(),
         config.polygon_api_key.clone(),
         ms_agg_tx.clone(),
         ms_agg_cmd_rx,
+        subscriptions.clone(),
     );
 
     tokio::spawn(async move {
         ms_agg_upstream.run().await;
     });
 
-    // Start fake data generator (sends to same channels as real upstreams)
-    let fake_generator = FakeDataGenerator::new(
-        firehose_tx.clone(),
-        ms_agg_tx.clone(),
-        subscriptions.clone(),
-    );
+    // Start the pluggable data source: a live exchange feed if one's
+    // configured, otherwise the fake generator so local dev needs no real
+    // credentials. Either way it feeds the same two channels as the real
+    // firehose/ms-aggregator upstreams above.
+    let data_source: Arc<dyn DataSource> = match (&config.live_data_source_url, live_cmd_rx) {
+        (Some(url), Some(rx)) => {
+            info!("Live data source enabled: {}", url);
+            Arc::new(LiveDataSource::new(url.clone(), rx))
+        }
+        _ => {
+            info!("Fake data generator started (subscribe to T.FAKETICKER, Q.FAKETICKER, A.FAKETICKER, or AM.FAKETICKER)");
+            Arc::new(FakeDataGenerator::new())
+        }
+    };
 
+    let data_source_firehose_tx = firehose_tx.clone();
+    let data_source_ms_agg_tx = ms_agg_tx.clone();
+    let data_source_subscriptions = subscriptions.clone();
     tokio::spawn(async move {
-        fake_generator.start().await;
+        data_source
+            .run(
+                data_source_firehose_tx,
+                data_source_ms_agg_tx,
+                data_source_subscriptions,
+            )
+            .await;
     });
 
-    info!("Fake data generator started (subscribe to T.FAKETICKER, Q.FAKETICKER, A.FAKETICKER, or AM.FAKETICKER)");
-
     // Start router
-    let router = Router::new(subscriptions.clone(), clients);
+    let router = Router::new(subscriptions.clone());
 
     // Route messages from BOTH upstreams to clients
     loop {
@@ -1,194 +1,586 @@
-use crate::types::{is_bar_subscription, is_ms_bar_subscription, ClientId};
+use crate::types::{is_bar_subscription, is_ms_bar_subscription, ClientId, StatusMessage};
 use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::{mpsc, Notify};
 use tokio::time::{Duration, Instant};
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
+
+const FIREHOSE_EVENT_TYPES: [&str; 4] = ["T", "Q", "LULD", "FMV"];
+const MS_AGGREGATOR_EVENT_TYPES: [&str; 2] = ["A", "AM"];
+
+/// Stable identifier for one distinct subscription key (exact "EV.SYM" or
+/// wildcard pattern). Allocated once per key and reused by every client that
+/// subscribes to the same key, so identical subscriptions from many clients
+/// collapse onto a single upstream slot instead of churning it per-client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SubscriptionId(u64);
+
+// One node per distinct subscription key, reference-counted by the clients
+// holding it.
+struct SubscriptionNode {
+    key: String,
+    clients: HashSet<ClientId>,
+    // Mirrors `clients.len()`; kept as an explicit counter so ref-counting
+    // stays correct even if callers start tracking clients outside the set.
+    count: usize,
+}
 
 pub struct SubscriptionManager {
-    // Client ID -> Their subscriptions (can include "*" for wildcard)
+    // Client ID -> Their subscription keys (exact and/or wildcard patterns)
     client_subs: HashMap<ClientId, HashSet<String>>,
-    
-    // Track who has wildcard
-    wildcard_clients: HashSet<ClientId>,
-    
-    // Symbol -> Set of clients (for specific subscriptions)
-    symbol_to_clients: HashMap<String, HashSet<ClientId>>,
-    
-    // Symbols to unsubscribe upstream (with timestamp for delayed cleanup)
+
+    // Normalized subscription key ("EV.SYM" or a wildcard pattern) -> its id
+    by_params: HashMap<String, SubscriptionId>,
+
+    // Subscription id -> ref-counted node
+    nodes: HashMap<SubscriptionId, SubscriptionNode>,
+
+    // Ids of nodes whose key is a wildcard pattern, tracked separately so
+    // matching a message doesn't require scanning every exact subscription.
+    pattern_ids: HashSet<SubscriptionId>,
+
+    // Monotonic counter backing SubscriptionId allocation
+    next_id: u64,
+
+    // Keys to unsubscribe upstream once their ref count hits zero (with
+    // timestamp for delayed cleanup)
     pending_unsubs: HashMap<String, Instant>,
+
+    // Owned delivery side: each registered client's outbound channel.
+    clients: HashMap<ClientId, mpsc::Sender<Arc<str>>>,
+
+    // Fired to tell a client's connection task to tear itself down after
+    // `dispatch` evicts it as a slow consumer.
+    evict_notify: HashMap<ClientId, Arc<Notify>>,
+
+    // Consecutive full-channel drops per client, reset on a successful send.
+    consecutive_drops: HashMap<ClientId, u32>,
+
+    // Consecutive drops before a client is evicted as a slow consumer.
+    slow_client_drop_threshold: u32,
+
+    // Outstanding bytes queued per client, incremented on every dispatch
+    // send and decremented by the client's own forward task as it drains
+    // its channel. Shared the same way as `evict_notify`.
+    queued_bytes: HashMap<ClientId, Arc<AtomicUsize>>,
+
+    // When a client first went over its queue limits, so a brief burst
+    // doesn't get it evicted before `slow_consumer_grace` has elapsed.
+    over_limit_since: HashMap<ClientId, Instant>,
+
+    // Per-client channel depth considered "over limit" for eviction
+    // purposes (the channel itself is still sized to this).
+    max_queued_items: usize,
+
+    // Per-client outstanding-bytes cap considered "over limit".
+    max_queued_bytes: usize,
+
+    // How long a client may sit over its queue limits before eviction.
+    slow_consumer_grace: Duration,
+
+    // Cap on distinct active subscription keys/patterns; enforced only
+    // when a `Subscribe` would allocate a genuinely new node.
+    max_active_subscriptions: usize,
+
+    // Latest serialized single-message payload for each concrete bar key
+    // ("A.AAPL", "AM.TSLA", "100Ms.SPY", ...), so a client subscribing to a
+    // bar channel can be handed a "current state" snapshot immediately
+    // instead of waiting for the next window to close.
+    bar_checkpoints: HashMap<String, Arc<str>>,
 }
 
 impl SubscriptionManager {
-    pub fn new() -> Self {
+    pub fn new(
+        slow_client_drop_threshold: u32,
+        max_queued_items: usize,
+        max_queued_bytes: usize,
+        slow_consumer_grace: Duration,
+        max_active_subscriptions: usize,
+    ) -> Self {
         Self {
             client_subs: HashMap::new(),
-            wildcard_clients: HashSet::new(),
-            symbol_to_clients: HashMap::new(),
+            by_params: HashMap::new(),
+            nodes: HashMap::new(),
+            pattern_ids: HashSet::new(),
+            next_id: 0,
             pending_unsubs: HashMap::new(),
+            clients: HashMap::new(),
+            evict_notify: HashMap::new(),
+            consecutive_drops: HashMap::new(),
+            slow_client_drop_threshold,
+            queued_bytes: HashMap::new(),
+            over_limit_since: HashMap::new(),
+            max_queued_items,
+            max_queued_bytes,
+            slow_consumer_grace,
+            max_active_subscriptions,
+            bar_checkpoints: HashMap::new(),
         }
     }
-    
-    pub fn add_subscription(&mut self, client_id: ClientId, params: &str) {
-        // Parse params: "T.AAPL,Q.AAPL,T.*" etc
+
+    /// Register a client's outbound channel so `dispatch` can deliver to it
+    /// directly. `evict_notify` is fired if this client is later evicted as a
+    /// slow consumer, so its connection task can tear itself down without
+    /// waiting for a send to fail. Returns the byte counter `dispatch` will
+    /// increment on every send, so the caller's forward task can decrement
+    /// it as messages are actually drained.
+    pub fn register_client(
+        &mut self,
+        client_id: ClientId,
+        tx: mpsc::Sender<Arc<str>>,
+        evict_notify: Arc<Notify>,
+    ) -> Arc<AtomicUsize> {
+        let queued_bytes = Arc::new(AtomicUsize::new(0));
+        self.clients.insert(client_id, tx);
+        self.evict_notify.insert(client_id, evict_notify);
+        self.queued_bytes.insert(client_id, queued_bytes.clone());
+        queued_bytes
+    }
+
+    // Whether `client_id` is currently parked over its item or byte queue
+    // cap. Item depth comes straight from the mpsc channel's own
+    // capacity/max_capacity rather than a separate counter.
+    fn is_over_queue_limit(&self, client_id: ClientId) -> bool {
+        let items_over = self.clients.get(&client_id).is_some_and(|tx| {
+            tx.max_capacity() - tx.capacity() >= self.max_queued_items
+        });
+        let bytes_over = self.queued_bytes.get(&client_id).is_some_and(|counter| {
+            counter.load(Ordering::Relaxed) >= self.max_queued_bytes
+        });
+        items_over || bytes_over
+    }
+
+    /// Filter `message` per client (same logic as
+    /// `get_filtered_messages_per_client`) and push each payload straight
+    /// into that client's bounded channel. A client whose channel is full
+    /// racks up a drop; once its consecutive drops cross
+    /// `slow_client_drop_threshold` it's evicted so one stalled consumer
+    /// can't back up delivery to everyone else. A client that stays over
+    /// its queue caps (item count or outstanding bytes) for longer than
+    /// `slow_consumer_grace` is evicted the same way, even if its sends
+    /// keep nominally succeeding.
+    pub fn dispatch(&mut self, message: &str) {
+        self.update_bar_checkpoints(message);
+
+        let filtered = self.get_filtered_messages_per_client(message);
+        if filtered.is_empty() {
+            return;
+        }
+
+        let mut to_evict: HashSet<ClientId> = HashSet::new();
+
+        for (client_id, payload) in filtered {
+            let Some(tx) = self.clients.get(&client_id) else {
+                continue;
+            };
+
+            match tx.try_send(payload.clone()) {
+                Ok(_) => {
+                    self.consecutive_drops.remove(&client_id);
+                    if let Some(counter) = self.queued_bytes.get(&client_id) {
+                        counter.fetch_add(payload.len(), Ordering::Relaxed);
+                    }
+                }
+                Err(_) => {
+                    let count = self.consecutive_drops.entry(client_id).or_insert(0);
+                    *count += 1;
+                    debug!("Client {} channel full, {} consecutive drops", client_id, count);
+                    if *count >= self.slow_client_drop_threshold {
+                        to_evict.insert(client_id);
+                    }
+                }
+            }
+
+            if self.is_over_queue_limit(client_id) {
+                let since = *self
+                    .over_limit_since
+                    .entry(client_id)
+                    .or_insert_with(Instant::now);
+                if since.elapsed() >= self.slow_consumer_grace {
+                    to_evict.insert(client_id);
+                }
+            } else {
+                self.over_limit_since.remove(&client_id);
+            }
+        }
+
+        for client_id in to_evict {
+            warn!("Evicting client {} as a slow consumer", client_id);
+            if let Some(tx) = self.clients.get(&client_id) {
+                let status = vec![StatusMessage {
+                    status: "slow_consumer".to_string(),
+                    message: "disconnected: slow consumer".to_string(),
+                }];
+                if let Ok(status_text) = serde_json::to_string(&status) {
+                    let _ = tx.try_send(Arc::from(status_text));
+                }
+            }
+            if let Some(notify) = self.evict_notify.get(&client_id) {
+                notify.notify_one();
+            }
+            self.remove_client(client_id);
+        }
+    }
+
+    /// Push a connection-state notice to every currently registered client,
+    /// bypassing subscription filtering entirely - e.g. so clients learn an
+    /// upstream dropped (and later recovered) even if nothing they're
+    /// subscribed to has changed.
+    pub fn broadcast_status(&self, status: StatusMessage) {
+        let Ok(status_text) = serde_json::to_string(&vec![status]) else {
+            return;
+        };
+        let payload: Arc<str> = Arc::from(status_text);
+        for tx in self.clients.values() {
+            let _ = tx.try_send(payload.clone());
+        }
+    }
+
+    // Cache the latest bar message per concrete key, so a client that
+    // subscribes after a window has already closed still gets an instant
+    // snapshot instead of waiting for the next one.
+    fn update_bar_checkpoints(&mut self, message: &str) {
+        use serde_json::Value;
+
+        let Ok(Value::Array(messages)) = serde_json::from_str::<Value>(message) else {
+            return;
+        };
+
+        for msg_value in &messages {
+            let (Some(ev), Some(sym)) = (
+                msg_value.get("ev").and_then(|v| v.as_str()),
+                msg_value.get("sym").and_then(|v| v.as_str()),
+            ) else {
+                continue;
+            };
+
+            let key = format!("{}.{}", ev, sym);
+            if !is_ms_bar_subscription(&key) {
+                continue;
+            }
+
+            if let Ok(serialized) = serde_json::to_string(msg_value) {
+                self.bar_checkpoints.insert(key, Arc::from(serialized));
+            }
+        }
+    }
+
+    /// Cached last-bar payloads matching `params` (the same comma-separated
+    /// exact-key/wildcard syntax accepted by `add_subscription`), for an
+    /// instant "current state" snapshot handed to a newly subscribing
+    /// client before the live stream catches up.
+    pub fn bar_checkpoints_for(&self, params: &str) -> Vec<Arc<str>> {
         let symbols = self.parse_symbols(params);
-        
+        let mut seen: HashSet<&str> = HashSet::new();
+        let mut result = Vec::new();
+
+        for symbol in &symbols {
+            for (key, payload) in &self.bar_checkpoints {
+                if matches(symbol, key) && seen.insert(key) {
+                    result.push(payload.clone());
+                }
+            }
+        }
+
+        result
+    }
+
+    fn allocate_id(&mut self) -> SubscriptionId {
+        let id = SubscriptionId(self.next_id);
+        self.next_id += 1;
+        id
+    }
+
+    /// Add `params` to `client_id`'s subscriptions. Joining a key some other
+    /// client already holds is always allowed; a key that would allocate a
+    /// genuinely new node is rejected once `max_active_subscriptions` is
+    /// reached, with the rejected symbols returned so the caller can report
+    /// them back to the client.
+    pub fn add_subscription(&mut self, client_id: ClientId, params: &str) -> Vec<String> {
+        // Parse params: "T.AAPL,Q.AAPL,T.*,*.AAPL" etc
+        let symbols = self.parse_symbols(params);
+        let mut rejected = Vec::new();
+
         for symbol in symbols {
-            if symbol == "*" {
-                // Client wants everything
-                self.wildcard_clients.insert(client_id);
-                info!("Client {} subscribed to wildcard", client_id);
-            } else {
-                // Specific symbol subscription
-                self.symbol_to_clients
-                    .entry(symbol.clone())
-                    .or_default()
-                    .insert(client_id);
-                debug!("Client {} subscribed to {}", client_id, symbol);
+            match self.by_params.get(&symbol).copied() {
+                Some(id) => {
+                    // Another client already holds this key: just join it.
+                    if let Some(node) = self.nodes.get_mut(&id) {
+                        if node.clients.insert(client_id) {
+                            node.count += 1;
+                        }
+                    }
+                    debug!("Client {} joined existing subscription {}", client_id, symbol);
+                }
+                None => {
+                    if self.nodes.len() >= self.max_active_subscriptions {
+                        warn!(
+                            "Rejecting subscription {} for client {}: at capacity ({})",
+                            symbol, client_id, self.max_active_subscriptions
+                        );
+                        rejected.push(symbol);
+                        continue;
+                    }
+
+                    let id = self.allocate_id();
+                    let mut clients = HashSet::new();
+                    clients.insert(client_id);
+                    self.nodes.insert(
+                        id,
+                        SubscriptionNode {
+                            key: symbol.clone(),
+                            clients,
+                            count: 1,
+                        },
+                    );
+                    self.by_params.insert(symbol.clone(), id);
+
+                    if is_pattern(&symbol) {
+                        self.pattern_ids.insert(id);
+                        info!("Client {} subscribed to pattern {}", client_id, symbol);
+                    } else {
+                        debug!("Client {} subscribed to {}", client_id, symbol);
+                    }
+                }
             }
-            
+
             self.client_subs
                 .entry(client_id)
                 .or_default()
                 .insert(symbol.clone());
-                
+
             // Remove from pending unsubs if it was scheduled
             self.pending_unsubs.remove(&symbol);
         }
+
+        rejected
     }
-    
+
     pub fn remove_subscription(&mut self, client_id: ClientId, params: &str) {
         let symbols = self.parse_symbols(params);
-        
+
         for symbol in symbols {
-            if symbol == "*" {
-                self.wildcard_clients.remove(&client_id);
-                info!("Client {} unsubscribed from wildcard", client_id);
-            } else {
-                if let Some(clients) = self.symbol_to_clients.get_mut(&symbol) {
-                    clients.remove(&client_id);
-                    
-                    // Schedule upstream unsub if no clients left
-                    if clients.is_empty() && self.wildcard_clients.is_empty() {
-                        // Schedule for removal in 30 seconds
-                        self.pending_unsubs.insert(symbol.clone(), Instant::now());
-                        debug!("Scheduled {} for upstream unsubscribe", symbol);
-                    }
-                }
-            }
-            
+            self.release(&symbol, client_id);
+
             if let Some(subs) = self.client_subs.get_mut(&client_id) {
                 subs.remove(&symbol);
             }
         }
     }
-    
-    pub fn get_filtered_messages_per_client(&self, message: &str) -> HashMap<ClientId, String> {
+
+    // Drop one client's hold on `key`. Once the node's ref count reaches
+    // zero it's retired and the key is handed to the delayed-unsubscribe path.
+    fn release(&mut self, key: &str, client_id: ClientId) {
+        let Some(id) = self.by_params.get(key).copied() else {
+            return;
+        };
+
+        let Some(node) = self.nodes.get_mut(&id) else {
+            return;
+        };
+
+        if node.clients.remove(&client_id) {
+            node.count = node.count.saturating_sub(1);
+        }
+
+        if node.clients.is_empty() {
+            self.nodes.remove(&id);
+            self.by_params.remove(key);
+            self.pattern_ids.remove(&id);
+            self.pending_unsubs.insert(key.to_string(), Instant::now());
+            debug!("Scheduled {} for upstream unsubscribe", key);
+        }
+    }
+
+    // Messages with no `ev`/`sym` field (status frames mixed into a batch)
+    // only reach bare-wildcard subscribers; this is a synthetic key for them
+    // so they can be bucketed the same way as every real "EV.SYM" key.
+    const NO_KEY_BUCKET: &'static str = "\0no-key\0";
+
+    /// Fan out `message` to every interested client, serializing each
+    /// distinct filtered payload exactly once. Clients are bucketed by the
+    /// exact set of keys they match *within this batch* — every client in a
+    /// bucket is byte-for-byte identical, most commonly every bare-wildcard
+    /// client sharing one bucket — so an `Arc<str>` is built once per bucket
+    /// and handed out as cheap clones instead of re-serializing per client.
+    pub fn get_filtered_messages_per_client(&self, message: &str) -> HashMap<ClientId, Arc<str>> {
         use serde_json::Value;
 
         let mut result = HashMap::new();
 
-        // Try to parse as JSON array
-        if let Ok(Value::Array(messages)) = serde_json::from_str::<Value>(message) {
-            // Build per-client filtered arrays
-            let mut client_messages: HashMap<ClientId, Vec<Value>> = HashMap::new();
-
-            // Initialize wildcard clients with empty arrays
-            for client_id in &self.wildcard_clients {
-                client_messages.insert(*client_id, Vec::new());
+        let Ok(Value::Array(messages)) = serde_json::from_str::<Value>(message) else {
+            // Not a JSON array (e.g. a status frame) - only bare wildcard
+            // subscribers get it, all sharing one Arc of the raw text.
+            let payload: Arc<str> = Arc::from(message);
+            for pattern_id in &self.pattern_ids {
+                if let Some(node) = self.nodes.get(pattern_id) {
+                    if node.key == "*" || node.key == ">" {
+                        for client_id in &node.clients {
+                            result.insert(*client_id, payload.clone());
+                        }
+                    }
+                }
             }
+            return result;
+        };
 
-            // Process each message in the array
-            for msg_value in messages {
-                // Extract sym and ev fields
-                if let (Some(sym), Some(ev)) = (
-                    msg_value.get("sym").and_then(|v| v.as_str()),
+        // Extract ev/sym keys once per message instead of per (message, client) pair.
+        let keys: Vec<Option<String>> = messages
+            .iter()
+            .map(|msg_value| {
+                match (
                     msg_value.get("ev").and_then(|v| v.as_str()),
+                    msg_value.get("sym").and_then(|v| v.as_str()),
                 ) {
-                    let subscription_key = format!("{}.{}", ev, sym);
+                    (Some(ev), Some(sym)) => Some(format!("{}.{}", ev, sym)),
+                    _ => None,
+                }
+            })
+            .collect();
 
-                    // Add to wildcard clients
-                    for client_id in &self.wildcard_clients {
-                        client_messages.get_mut(client_id)
-                            .unwrap()
-                            .push(msg_value.clone());
-                    }
+        let distinct_keys: HashSet<&str> = keys.iter().filter_map(|k| k.as_deref()).collect();
 
-                    // Add to specific subscribers
-                    if let Some(clients) = self.symbol_to_clients.get(&subscription_key) {
-                        for client_id in clients {
-                            client_messages.entry(*client_id)
-                                .or_insert_with(Vec::new)
-                                .push(msg_value.clone());
-                        }
-                    }
-                } else {
-                    // Message doesn't have sym/ev fields - send to wildcard clients only
-                    for client_id in &self.wildcard_clients {
-                        client_messages.get_mut(client_id)
-                            .unwrap()
-                            .push(msg_value.clone());
+        // Recipients per distinct key, computed once per key (not once per
+        // message occurrence of that key).
+        let mut recipients_by_key: HashMap<&str, HashSet<ClientId>> = HashMap::new();
+        for &key in &distinct_keys {
+            let mut recipients: HashSet<ClientId> = HashSet::new();
+
+            if let Some(node) = self.by_params.get(key).and_then(|id| self.nodes.get(id)) {
+                recipients.extend(&node.clients);
+            }
+
+            for pattern_id in &self.pattern_ids {
+                if let Some(node) = self.nodes.get(pattern_id) {
+                    if matches(&node.key, key) {
+                        recipients.extend(&node.clients);
                     }
                 }
             }
 
-            // Serialize each client's filtered array
-            for (client_id, msgs) in client_messages {
-                if !msgs.is_empty() {
-                    if let Ok(serialized) = serde_json::to_string(&msgs) {
-                        result.insert(client_id, serialized);
+            recipients_by_key.insert(key, recipients);
+        }
+
+        // Invert into "which keys (from this batch) does each client match",
+        // so clients with an identical matched-key set can share one payload.
+        let mut keys_by_client: HashMap<ClientId, HashSet<&str>> = HashMap::new();
+        for (&key, recipients) in &recipients_by_key {
+            for client_id in recipients {
+                keys_by_client.entry(*client_id).or_default().insert(key);
+            }
+        }
+
+        if keys.iter().any(|k| k.is_none()) {
+            for pattern_id in &self.pattern_ids {
+                if let Some(node) = self.nodes.get(pattern_id) {
+                    if node.key == "*" || node.key == ">" {
+                        for client_id in &node.clients {
+                            keys_by_client.entry(*client_id).or_default().insert(Self::NO_KEY_BUCKET);
+                        }
                     }
                 }
             }
-        } else {
-            // Not a JSON array - send to wildcard clients only
-            for client_id in &self.wildcard_clients {
-                result.insert(*client_id, message.to_string());
+        }
+
+        // Bucket clients by their matched-key set.
+        let mut buckets: HashMap<Vec<&str>, Vec<ClientId>> = HashMap::new();
+        for (client_id, matched_keys) in keys_by_client {
+            let mut sorted: Vec<&str> = matched_keys.into_iter().collect();
+            sorted.sort_unstable();
+            buckets.entry(sorted).or_default().push(client_id);
+        }
+
+        for (matched_keys, client_ids) in buckets {
+            let matched_key_set: HashSet<&str> = matched_keys.into_iter().collect();
+
+            // Zero-copy until serialization: collect references, not clones.
+            let filtered: Vec<&Value> = messages
+                .iter()
+                .zip(&keys)
+                .filter_map(|(msg_value, key)| {
+                    let in_bucket = match key {
+                        Some(k) => matched_key_set.contains(k.as_str()),
+                        None => matched_key_set.contains(Self::NO_KEY_BUCKET),
+                    };
+                    in_bucket.then_some(msg_value)
+                })
+                .collect();
+
+            if filtered.is_empty() {
+                continue;
+            }
+
+            let Ok(serialized) = serde_json::to_string(&filtered) else {
+                continue;
+            };
+            let payload: Arc<str> = Arc::from(serialized);
+
+            for client_id in client_ids {
+                result.insert(client_id, payload.clone());
             }
         }
 
         result
     }
-    
+
     // Get subscriptions for Firehose (trades, quotes, but NOT bars)
     pub fn get_firehose_subscription(&self) -> String {
-        if !self.wildcard_clients.is_empty() {
-            // Wildcard for firehose: only non-bar types
-            // T = Trades, Q = Quotes, LULD, FMV
-            // Note: ALL bars (A.*, AM.*, *Ms.*) go to ms-aggregator
-            "T.*,Q.*,LULD.*,FMV.*".to_string()
-        } else {
-            // Build subscription string from non-bar symbols
-            // Includes: T.*, Q.*, LULD.*, FMV.* but NOT A.*, AM.*, or *Ms.*
-            self.symbol_to_clients.keys()
-                .filter(|s| !is_bar_subscription(s))
-                .cloned()
-                .collect::<Vec<_>>()
-                .join(",")
-        }
+        self.build_upstream_subscription(&FIREHOSE_EVENT_TYPES, false)
     }
 
     // Get subscriptions for Ms-Aggregator (all bar types: A.*, AM.*, and *Ms.*)
     pub fn get_ms_aggregator_subscription(&self) -> String {
-        if !self.wildcard_clients.is_empty() {
-            // Wildcard for ms-aggregator: all native bars
-            // A.* = Second bars, AM.* = Minute bars
-            // NOTE: Wildcard does NOT include millisecond bars (*Ms.*)
-            // Clients must explicitly subscribe to millisecond bars (e.g., "500Ms.TSLA")
-            "A.*,AM.*".to_string()
-        } else {
-            // Build subscription string from all bar symbols
-            // Includes: A.*, AM.*, 100Ms.*, 250Ms.*, 500Ms.*, etc.
-            self.symbol_to_clients.keys()
-                .filter(|s| is_bar_subscription(s))
-                .cloned()
-                .collect::<Vec<_>>()
-                .join(",")
+        self.build_upstream_subscription(&MS_AGGREGATOR_EVENT_TYPES, true)
+    }
+
+    // Translate exact keys and patterns into the concrete upstream subscription
+    // strings for one destination (firehose or ms-aggregator). `event_types`
+    // are the event prefixes that belong to this destination, used to expand
+    // a symbol-only pattern like "*.AAPL" across them.
+    fn build_upstream_subscription(&self, event_types: &[&str], is_ms_agg: bool) -> String {
+        let mut subs: HashSet<String> = HashSet::new();
+
+        for node in self.nodes.values() {
+            if !is_pattern(&node.key) {
+                if is_bar_subscription(&node.key) == is_ms_agg {
+                    subs.insert(node.key.clone());
+                }
+                continue;
+            }
+
+            if node.key == "*" || node.key == ">" {
+                // Bare wildcard: every event type this destination carries.
+                for ev in event_types {
+                    subs.insert(format!("{}.*", ev));
+                }
+                continue;
+            }
+
+            let mut tokens = node.key.splitn(2, '.');
+            let (ev_token, sym_token) = match (tokens.next(), tokens.next()) {
+                (Some(ev), Some(sym)) => (ev, sym),
+                _ => continue,
+            };
+
+            if ev_token == "*" || ev_token == ">" {
+                // "*.AAPL": expand across this destination's event types.
+                for ev in event_types {
+                    subs.insert(format!("{}.{}", ev, sym_token));
+                }
+            } else if is_bar_subscription(&node.key) == is_ms_agg {
+                // "T.*"-style: a single concrete event type, forward as-is.
+                subs.insert(node.key.clone());
+            }
         }
+
+        subs.into_iter().collect::<Vec<_>>().join(",")
     }
-    
+
     pub fn cleanup_pending_unsubs(&mut self) -> Vec<String> {
         let now = Instant::now();
         let mut to_unsub = Vec::new();
-        
+
         self.pending_unsubs.retain(|symbol, time| {
             if now.duration_since(*time) > Duration::from_secs(30) {
                 to_unsub.push(symbol.clone());
@@ -197,10 +589,10 @@ impl SubscriptionManager {
                 true // Keep in pending
             }
         });
-        
+
         to_unsub
     }
-    
+
     #[allow(dead_code)]
     pub fn has_clients(&self) -> bool {
         !self.client_subs.is_empty()
@@ -208,40 +600,67 @@ impl SubscriptionManager {
 
     // Check if there's a subscriber for a specific subscription key (e.g., "T.AAPL")
     pub fn has_subscription(&self, subscription_key: &str) -> bool {
-        // Wildcard clients get everything
-        if !self.wildcard_clients.is_empty() {
+        if self
+            .by_params
+            .get(subscription_key)
+            .and_then(|id| self.nodes.get(id))
+            .map(|node| !node.clients.is_empty())
+            .unwrap_or(false)
+        {
             return true;
         }
 
-        // Check if anyone is subscribed to this specific key
-        self.symbol_to_clients
-            .get(subscription_key)
-            .map(|clients| !clients.is_empty())
-            .unwrap_or(false)
+        self.pattern_ids.iter().any(|id| {
+            self.nodes
+                .get(id)
+                .map(|node| !node.clients.is_empty() && matches(&node.key, subscription_key))
+                .unwrap_or(false)
+        })
     }
 
     pub fn remove_client(&mut self, client_id: ClientId) {
         // Get all their subscriptions
         if let Some(subs) = self.client_subs.remove(&client_id) {
             for symbol in subs {
-                if symbol == "*" {
-                    self.wildcard_clients.remove(&client_id);
-                } else {
-                    if let Some(clients) = self.symbol_to_clients.get_mut(&symbol) {
-                        clients.remove(&client_id);
-                        if clients.is_empty() && self.wildcard_clients.is_empty() {
-                            self.pending_unsubs.insert(symbol, Instant::now());
-                        }
-                    }
-                }
+                self.release(&symbol, client_id);
             }
         }
+        self.clients.remove(&client_id);
+        self.evict_notify.remove(&client_id);
+        self.consecutive_drops.remove(&client_id);
+        self.queued_bytes.remove(&client_id);
+        self.over_limit_since.remove(&client_id);
         info!("Removed all subscriptions for client {}", client_id);
     }
-    
+
+    // Operator-facing snapshot of current fan-out load, for introspection
+    // without walking the internal maps by hand.
+    pub fn stats(&self) -> SubscriptionStats {
+        let mut counts_by_event_type: HashMap<String, usize> = HashMap::new();
+        let mut wildcard_clients: HashSet<ClientId> = HashSet::new();
+
+        for node in self.nodes.values() {
+            if let Some(category) = event_type_category(&node.key) {
+                *counts_by_event_type.entry(category.to_string()).or_insert(0) += node.count;
+            }
+
+            if node.key == "*" || node.key == ">" {
+                wildcard_clients.extend(&node.clients);
+            }
+        }
+
+        SubscriptionStats {
+            num_active_subscriptions: self.nodes.len(),
+            counts_by_event_type,
+            num_wildcard_clients: wildcard_clients.len(),
+            num_pending_unsubs: self.pending_unsubs.len(),
+        }
+    }
+
     fn parse_symbols(&self, params: &str) -> Vec<String> {
-        // Parse "T.AAPL,Q.AAPL,T.*" format
-        // Keep the full TYPE.SYMBOL format to track per-message-type subscriptions
+        // Parse "T.AAPL,Q.AAPL,T.*,*.AAPL,*" format. Unlike exact keys,
+        // patterns are kept verbatim (not collapsed to a bare "*") so
+        // `T.*` and `*.AAPL` stay distinct subscriptions.
         let mut symbols = HashSet::new();
 
         for item in params.split(',') {
@@ -250,19 +669,70 @@ impl SubscriptionManager {
                 continue;
             }
 
-            // Check for wildcard (like T.* or just *)
-            if item.contains("*") {
-                symbols.insert("*".to_string());
-            } else {
-                // Keep the full TYPE.SYMBOL format (e.g., "T.AAPL", "Q.MSFT")
-                symbols.insert(item.to_string());
-            }
+            symbols.insert(item.to_string());
         }
 
         symbols.into_iter().collect()
     }
 }
 
+#[derive(Debug, Default)]
+pub struct SubscriptionStats {
+    pub num_active_subscriptions: usize,
+    pub counts_by_event_type: HashMap<String, usize>,
+    pub num_wildcard_clients: usize,
+    pub num_pending_unsubs: usize,
+}
+
+// A pattern is any subscription string containing a wildcard token.
+fn is_pattern(symbol: &str) -> bool {
+    symbol.contains('*') || symbol.contains('>')
+}
+
+// Canonical event-type bucket for a subscription key, used by `stats()`.
+// Custom bar intervals ("100Ms.SPY", "250Ms.*", ...) all collapse into "Ms".
+fn event_type_category(key: &str) -> Option<&'static str> {
+    let ev_token = key.split('.').next()?;
+
+    match ev_token {
+        "T" => Some("T"),
+        "Q" => Some("Q"),
+        "A" => Some("A"),
+        "AM" => Some("AM"),
+        "*" | ">" => None,
+        _ => {
+            let digits = ev_token.strip_suffix("Ms")?;
+            (!digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit())).then_some("Ms")
+        }
+    }
+}
+
+// NATS-style subject matching: both `pattern` and `key` are dot-separated
+// token lists. `*` matches exactly one token at that position; `>` (or a
+// bare `*`/`>` pattern) matches all remaining tokens, so `*` alone matches
+// anything.
+fn matches(pattern: &str, key: &str) -> bool {
+    if pattern == "*" || pattern == ">" {
+        return true;
+    }
+
+    let pattern_tokens: Vec<&str> = pattern.split('.').collect();
+    let key_tokens: Vec<&str> = key.split('.').collect();
+
+    for (i, token) in pattern_tokens.iter().enumerate() {
+        if *token == ">" {
+            return true;
+        }
+
+        match key_tokens.get(i) {
+            Some(key_token) if *token == "*" || token == key_token => continue,
+            _ => return false,
+        }
+    }
+
+    pattern_tokens.len() == key_tokens.len()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -270,19 +740,19 @@ mod tests {
 
     #[test]
     fn test_firehose_subscription_no_clients() {
-        let mgr = SubscriptionManager::new();
+        let mgr = SubscriptionManager::new(50, 100, 1_048_576, Duration::from_secs(10), 10_000);
         assert_eq!(mgr.get_firehose_subscription(), "");
     }
 
     #[test]
     fn test_ms_aggregator_subscription_no_clients() {
-        let mgr = SubscriptionManager::new();
+        let mgr = SubscriptionManager::new(50, 100, 1_048_576, Duration::from_secs(10), 10_000);
         assert_eq!(mgr.get_ms_aggregator_subscription(), "");
     }
 
     #[test]
     fn test_firehose_subscription_wildcard() {
-        let mut mgr = SubscriptionManager::new();
+        let mut mgr = SubscriptionManager::new(50, 100, 1_048_576, Duration::from_secs(10), 10_000);
         let client_id = Uuid::new_v4();
 
         mgr.add_subscription(client_id, "*");
@@ -298,7 +768,7 @@ mod tests {
 
     #[test]
     fn test_ms_aggregator_subscription_wildcard() {
-        let mut mgr = SubscriptionManager::new();
+        let mut mgr = SubscriptionManager::new(50, 100, 1_048_576, Duration::from_secs(10), 10_000);
         let client_id = Uuid::new_v4();
 
         mgr.add_subscription(client_id, "*");
@@ -313,7 +783,7 @@ mod tests {
 
     #[test]
     fn test_split_subscriptions_by_type() {
-        let mut mgr = SubscriptionManager::new();
+        let mut mgr = SubscriptionManager::new(50, 100, 1_048_576, Duration::from_secs(10), 10_000);
         let client_id = Uuid::new_v4();
 
         // Subscribe to mix of bars and non-bars
@@ -339,7 +809,7 @@ mod tests {
 
     #[test]
     fn test_only_bar_subscriptions() {
-        let mut mgr = SubscriptionManager::new();
+        let mut mgr = SubscriptionManager::new(50, 100, 1_048_576, Duration::from_secs(10), 10_000);
         let client_id = Uuid::new_v4();
 
         mgr.add_subscription(client_id, "A.AAPL,AM.TSLA,250Ms.NVDA");
@@ -358,7 +828,7 @@ mod tests {
 
     #[test]
     fn test_only_non_bar_subscriptions() {
-        let mut mgr = SubscriptionManager::new();
+        let mut mgr = SubscriptionManager::new(50, 100, 1_048_576, Duration::from_secs(10), 10_000);
         let client_id = Uuid::new_v4();
 
         mgr.add_subscription(client_id, "T.AAPL,Q.TSLA,LULD.NVDA");
@@ -377,7 +847,7 @@ mod tests {
 
     #[test]
     fn test_multiple_clients_different_types() {
-        let mut mgr = SubscriptionManager::new();
+        let mut mgr = SubscriptionManager::new(50, 100, 1_048_576, Duration::from_secs(10), 10_000);
         let client1 = Uuid::new_v4();
         let client2 = Uuid::new_v4();
 
@@ -394,4 +864,260 @@ mod tests {
         assert!(firehose_sub.contains("T.AAPL"));
         assert!(ms_agg_sub.contains("A.AAPL"));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_matches_token_wildcard() {
+        assert!(matches("T.*", "T.AAPL"));
+        assert!(!matches("T.*", "Q.AAPL"));
+        assert!(matches("*.AAPL", "T.AAPL"));
+        assert!(matches("*.AAPL", "Q.AAPL"));
+        assert!(!matches("*.AAPL", "T.TSLA"));
+        assert!(matches("*", "T.AAPL"));
+        assert!(matches("*", "anything.at.all"));
+        assert!(!matches("T.AAPL", "T.TSLA"));
+    }
+
+    #[test]
+    fn test_single_event_type_wildcard_does_not_over_subscribe() {
+        let mut mgr = SubscriptionManager::new(50, 100, 1_048_576, Duration::from_secs(10), 10_000);
+        let client_id = Uuid::new_v4();
+
+        // "T.*" should only pull trades upstream, not everything.
+        mgr.add_subscription(client_id, "T.*");
+
+        let firehose_sub = mgr.get_firehose_subscription();
+        assert_eq!(firehose_sub, "T.*");
+        assert_eq!(mgr.get_ms_aggregator_subscription(), "");
+    }
+
+    #[test]
+    fn test_symbol_wildcard_expands_per_destination() {
+        let mut mgr = SubscriptionManager::new(50, 100, 1_048_576, Duration::from_secs(10), 10_000);
+        let client_id = Uuid::new_v4();
+
+        // "*.AAPL" should expand to every event type, split by destination.
+        mgr.add_subscription(client_id, "*.AAPL");
+
+        let firehose_sub = mgr.get_firehose_subscription();
+        assert!(firehose_sub.contains("T.AAPL"));
+        assert!(firehose_sub.contains("Q.AAPL"));
+        assert!(firehose_sub.contains("LULD.AAPL"));
+        assert!(firehose_sub.contains("FMV.AAPL"));
+
+        let ms_agg_sub = mgr.get_ms_aggregator_subscription();
+        assert!(ms_agg_sub.contains("A.AAPL"));
+        assert!(ms_agg_sub.contains("AM.AAPL"));
+    }
+
+    #[test]
+    fn test_filtered_messages_dedup_exact_and_pattern() {
+        let mut mgr = SubscriptionManager::new(50, 100, 1_048_576, Duration::from_secs(10), 10_000);
+        let client_id = Uuid::new_v4();
+
+        mgr.add_subscription(client_id, "T.AAPL,T.*");
+
+        let message = r#"[{"ev":"T","sym":"AAPL","price":100}]"#;
+        let filtered = mgr.get_filtered_messages_per_client(message);
+
+        let client_msgs: Vec<serde_json::Value> =
+            serde_json::from_str(filtered.get(&client_id).unwrap()).unwrap();
+        assert_eq!(client_msgs.len(), 1);
+    }
+
+    #[test]
+    fn test_filtered_messages_per_symbol_wildcard() {
+        let mut mgr = SubscriptionManager::new(50, 100, 1_048_576, Duration::from_secs(10), 10_000);
+        let client_id = Uuid::new_v4();
+
+        mgr.add_subscription(client_id, "*.AAPL");
+
+        let message = r#"[{"ev":"T","sym":"AAPL"},{"ev":"Q","sym":"TSLA"}]"#;
+        let filtered = mgr.get_filtered_messages_per_client(message);
+
+        let client_msgs: Vec<serde_json::Value> =
+            serde_json::from_str(filtered.get(&client_id).unwrap()).unwrap();
+        assert_eq!(client_msgs.len(), 1);
+        assert_eq!(client_msgs[0]["sym"], "AAPL");
+    }
+
+    #[test]
+    fn test_duplicate_client_subscriptions_collapse_to_one_node() {
+        let mut mgr = SubscriptionManager::new(50, 100, 1_048_576, Duration::from_secs(10), 10_000);
+        let client1 = Uuid::new_v4();
+        let client2 = Uuid::new_v4();
+
+        // Two clients asking for the exact same key should collapse onto a
+        // single SubscriptionId/upstream slot, not churn it twice.
+        mgr.add_subscription(client1, "T.AAPL");
+        mgr.add_subscription(client2, "T.AAPL");
+
+        assert_eq!(mgr.get_firehose_subscription(), "T.AAPL");
+        assert_eq!(mgr.stats().num_active_subscriptions, 1);
+
+        mgr.remove_subscription(client1, "T.AAPL");
+        // Still one client holding it: the upstream subscription must stay.
+        assert_eq!(mgr.get_firehose_subscription(), "T.AAPL");
+
+        mgr.remove_subscription(client2, "T.AAPL");
+        assert_eq!(mgr.get_firehose_subscription(), "");
+        assert_eq!(mgr.stats().num_active_subscriptions, 0);
+        assert_eq!(mgr.cleanup_pending_unsubs().len(), 0); // not yet 30s old
+    }
+
+    #[test]
+    fn test_stats_counts_by_event_type_and_wildcards() {
+        let mut mgr = SubscriptionManager::new(50, 100, 1_048_576, Duration::from_secs(10), 10_000);
+        let client_id = Uuid::new_v4();
+
+        mgr.add_subscription(client_id, "T.AAPL,Q.AAPL,A.AAPL,AM.AAPL,100Ms.SPY,*");
+
+        let stats = mgr.stats();
+        assert_eq!(stats.num_active_subscriptions, 6);
+        assert_eq!(stats.counts_by_event_type.get("T"), Some(&1));
+        assert_eq!(stats.counts_by_event_type.get("Q"), Some(&1));
+        assert_eq!(stats.counts_by_event_type.get("A"), Some(&1));
+        assert_eq!(stats.counts_by_event_type.get("AM"), Some(&1));
+        assert_eq!(stats.counts_by_event_type.get("Ms"), Some(&1));
+        assert_eq!(stats.num_wildcard_clients, 1);
+        assert_eq!(stats.num_pending_unsubs, 0);
+    }
+
+    #[test]
+    fn test_stats_tracks_pending_unsubs() {
+        let mut mgr = SubscriptionManager::new(50, 100, 1_048_576, Duration::from_secs(10), 10_000);
+        let client_id = Uuid::new_v4();
+
+        mgr.add_subscription(client_id, "T.AAPL");
+        mgr.remove_subscription(client_id, "T.AAPL");
+
+        assert_eq!(mgr.stats().num_active_subscriptions, 0);
+        assert_eq!(mgr.stats().num_pending_unsubs, 1);
+    }
+
+    #[test]
+    fn test_dispatch_delivers_to_registered_client() {
+        let mut mgr = SubscriptionManager::new(50, 100, 1_048_576, Duration::from_secs(10), 10_000);
+        let client_id = Uuid::new_v4();
+        let (tx, mut rx) = mpsc::channel(10);
+
+        mgr.add_subscription(client_id, "T.AAPL");
+        mgr.register_client(client_id, tx, Arc::new(Notify::new()));
+
+        mgr.dispatch(r#"[{"ev":"T","sym":"AAPL","price":100}]"#);
+
+        let received = rx.try_recv().expect("client should have received a message");
+        assert!(received.contains("AAPL"));
+    }
+
+    #[test]
+    fn test_filtered_messages_share_payload_across_bucket() {
+        // Two clients with identical effective subscriptions (both bare
+        // wildcards) must land in the same bucket and get the exact same
+        // Arc allocation back, not just equal contents.
+        let mut mgr = SubscriptionManager::new(50, 100, 1_048_576, Duration::from_secs(10), 10_000);
+        let client1 = Uuid::new_v4();
+        let client2 = Uuid::new_v4();
+
+        mgr.add_subscription(client1, "*");
+        mgr.add_subscription(client2, "*");
+
+        let message = r#"[{"ev":"T","sym":"AAPL","price":100}]"#;
+        let filtered = mgr.get_filtered_messages_per_client(message);
+
+        let payload1 = filtered.get(&client1).unwrap();
+        let payload2 = filtered.get(&client2).unwrap();
+        assert!(Arc::ptr_eq(payload1, payload2));
+    }
+
+    #[test]
+    fn test_dispatch_evicts_slow_consumer() {
+        // Tiny threshold and channel so a handful of full-channel drops evicts.
+        let mut mgr = SubscriptionManager::new(2, 100, 1_048_576, Duration::from_secs(10), 10_000);
+        let client_id = Uuid::new_v4();
+        let (tx, rx) = mpsc::channel(1);
+        let evict_notify = Arc::new(Notify::new());
+
+        mgr.add_subscription(client_id, "T.*");
+        mgr.register_client(client_id, tx, evict_notify.clone());
+
+        let message = r#"[{"ev":"T","sym":"AAPL","price":100}]"#;
+
+        // Fill the channel, then keep dispatching without draining it so
+        // every subsequent send fails as "full".
+        mgr.dispatch(message);
+        mgr.dispatch(message);
+        mgr.dispatch(message);
+
+        // Evicted: no longer subscribed, and the connection task would have
+        // been woken to tear itself down.
+        assert!(!mgr.has_subscription("T.AAPL"));
+        assert_eq!(mgr.stats().num_active_subscriptions, 0);
+        drop(rx);
+    }
+
+    #[test]
+    fn test_add_subscription_rejects_past_capacity() {
+        let mut mgr = SubscriptionManager::new(50, 100, 1_048_576, Duration::from_secs(10), 1);
+        let client_id = Uuid::new_v4();
+
+        // First new key fills the single available slot.
+        let rejected = mgr.add_subscription(client_id, "T.AAPL");
+        assert!(rejected.is_empty());
+
+        // A second, distinct key is over capacity and should be rejected...
+        let rejected = mgr.add_subscription(client_id, "Q.AAPL");
+        assert_eq!(rejected, vec!["Q.AAPL".to_string()]);
+
+        // ...but joining the existing key is always allowed.
+        let rejected = mgr.add_subscription(client_id, "T.AAPL");
+        assert!(rejected.is_empty());
+    }
+
+    #[test]
+    fn test_dispatch_evicts_client_over_byte_limit_past_grace() {
+        // Zero grace so the very first over-limit dispatch evicts.
+        let mut mgr = SubscriptionManager::new(50, 100, 1, Duration::from_secs(0), 10_000);
+        let client_id = Uuid::new_v4();
+        let (tx, rx) = mpsc::channel(10);
+        let evict_notify = Arc::new(Notify::new());
+
+        mgr.add_subscription(client_id, "T.*");
+        mgr.register_client(client_id, tx, evict_notify.clone());
+
+        // Channel has plenty of headroom, but the 1-byte cap is blown by the
+        // first message, so eviction comes from the byte check, not drops.
+        mgr.dispatch(r#"[{"ev":"T","sym":"AAPL","price":100}]"#);
+
+        assert!(!mgr.has_subscription("T.AAPL"));
+        assert_eq!(mgr.stats().num_active_subscriptions, 0);
+        drop(rx);
+    }
+
+    #[test]
+    fn test_bar_checkpoints_cached_on_dispatch_and_matched_on_subscribe() {
+        let mut mgr = SubscriptionManager::new(50, 100, 1_048_576, Duration::from_secs(10), 10_000);
+
+        // No subscribers needed for the checkpoint to get cached - dispatch
+        // records the latest bar per key regardless of who's listening.
+        mgr.dispatch(r#"[{"ev":"AM","sym":"AAPL","o":1,"c":2}]"#);
+
+        let exact = mgr.bar_checkpoints_for("AM.AAPL");
+        assert_eq!(exact.len(), 1);
+        assert!(exact[0].contains("AAPL"));
+
+        let via_wildcard = mgr.bar_checkpoints_for("AM.*");
+        assert_eq!(via_wildcard.len(), 1);
+
+        assert!(mgr.bar_checkpoints_for("AM.TSLA").is_empty());
+    }
+
+    #[test]
+    fn test_bar_checkpoints_ignore_non_bar_messages() {
+        let mut mgr = SubscriptionManager::new(50, 100, 1_048_576, Duration::from_secs(10), 10_000);
+
+        mgr.dispatch(r#"[{"ev":"T","sym":"AAPL","price":100}]"#);
+
+        assert!(mgr.bar_checkpoints_for("T.AAPL").is_empty());
+        assert!(mgr.bar_checkpoints_for("*").is_empty());
+    }
+}
@@ -1,46 +1,107 @@
-use crate::types::Cluster;
+use crate::subscription_manager::SubscriptionManager;
+use crate::types::{ClientMessage, Cluster, StatusMessage};
 use anyhow::Result;
 use futures_util::{SinkExt, StreamExt};
-use tokio::sync::mpsc;
-use tokio::time::{interval, Duration};
-use tokio_tungstenite::{connect_async, tungstenite::Message};
+use std::sync::Arc;
+use tokio::sync::{mpsc, Mutex};
+use tokio::time::{interval, Duration, Instant};
+use tokio_tungstenite::{connect_async, tungstenite::Message, MaybeTlsStream, WebSocketStream};
 use tracing::{debug, error, info, warn};
 
+type UpstreamWrite = futures_util::stream::SplitSink<
+    WebSocketStream<MaybeTlsStream<tokio::net::TcpStream>>,
+    Message,
+>;
+
+// Exponential backoff bounds for reconnect attempts.
+const RECONNECT_BASE_DELAY: Duration = Duration::from_secs(1);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(60);
+// A connection that stays up at least this long resets the backoff.
+const RECONNECT_STABLE_AFTER: Duration = Duration::from_secs(60);
+
+// How long a connection may go without any inbound activity (message or
+// pong) before the health probe gives up on it and forces a reconnect.
+const STALE_CONNECTION_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// Which aggregate subscription set to replay after a reconnect - each
+/// upstream only cares about its own half of the client subscriptions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpstreamKind {
+    Firehose,
+    MsAggregator,
+}
+
 pub struct UpstreamConnection {
     cluster: Cluster,
+    kind: UpstreamKind,
     firehose_url: String,
     api_key: String,
     tx: mpsc::Sender<String>,
     rx_cmd: mpsc::Receiver<String>,
+    subscriptions: Arc<Mutex<SubscriptionManager>>,
 }
 
 impl UpstreamConnection {
     pub fn new(
         cluster: Cluster,
+        kind: UpstreamKind,
         firehose_url: String,
         api_key: String,
         tx: mpsc::Sender<String>,
         rx_cmd: mpsc::Receiver<String>,
+        subscriptions: Arc<Mutex<SubscriptionManager>>,
     ) -> Self {
         Self {
             cluster,
+            kind,
             firehose_url,
             api_key,
             tx,
             rx_cmd,
+            subscriptions,
         }
     }
 
+    /// Supervise the upstream connection for as long as the process runs:
+    /// connect, forward until it drops, then reconnect with exponential
+    /// backoff (capped at `RECONNECT_MAX_DELAY`, reset once a connection
+    /// stays up). Clients are told about each transition via a
+    /// `StatusMessage` so "why did my feed go quiet" is never silent.
     pub async fn run(mut self) {
+        let mut backoff = RECONNECT_BASE_DELAY;
+        let mut reconnecting = false;
+
         loop {
-            if let Err(e) = self.connect_and_forward().await {
-                error!("{} upstream connection error: {}", self.cluster, e);
-                tokio::time::sleep(Duration::from_secs(5)).await;
+            let connected_at = Instant::now();
+            match self.connect_and_forward(reconnecting).await {
+                Ok(()) => {
+                    // connect_and_forward returns Ok whenever its loop ends
+                    // without a transport error (close frame, failed send or
+                    // ping, stale-connection probe); still worth a reconnect,
+                    // so treat it the same as an error.
+                }
+                Err(e) => {
+                    error!("{} upstream connection error: {}", self.cluster, e);
+                }
+            }
+
+            if connected_at.elapsed() >= RECONNECT_STABLE_AFTER {
+                backoff = RECONNECT_BASE_DELAY;
             }
+
+            self.subscriptions.lock().await.broadcast_status(StatusMessage {
+                status: "upstream_disconnected".to_string(),
+                message: format!("{} upstream connection lost, reconnecting", self.cluster),
+            });
+
+            warn!("{} upstream reconnecting in {:?}", self.cluster, backoff);
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(RECONNECT_MAX_DELAY);
+            reconnecting = true;
         }
     }
 
-    async fn connect_and_forward(&mut self) -> Result<()> {
+    async fn connect_and_forward(&mut self, reconnecting: bool) -> Result<()> {
         // Connect to upstream (firehose or ms-aggregator)
         info!("Connecting to upstream at {}", self.firehose_url);
 
@@ -57,13 +118,27 @@ impl UpstreamConnection {
         write.send(Message::Text(auth_msg.to_string())).await?;
         debug!("{} sent auth to upstream", self.cluster);
 
+        if reconnecting {
+            self.replay_subscriptions(&mut write).await?;
+            self.subscriptions.lock().await.broadcast_status(StatusMessage {
+                status: "upstream_reconnected".to_string(),
+                message: format!("{} upstream connection restored", self.cluster),
+            });
+        }
+
         // Simple ping every 30 seconds to keep connection alive
         let mut ping_interval = interval(Duration::from_secs(30));
+        // Separate from the ping cadence: if nothing's come in for this long
+        // (no messages, no pongs), the socket is probably dead even though
+        // tungstenite hasn't told us yet.
+        let mut health_check_interval = interval(Duration::from_secs(30));
+        let mut last_activity = Instant::now();
 
         loop {
             tokio::select! {
                 // Forward messages from firehose to router
                 Some(msg) = read.next() => {
+                    last_activity = Instant::now();
                     match msg? {
                         Message::Text(text) => {
                             debug!("{} received: {}", self.cluster, text);
@@ -75,11 +150,14 @@ impl UpstreamConnection {
                             warn!("{} firehose connection closed", self.cluster);
                             break;
                         }
+                        Message::Pong(_) => {
+                            debug!("{} received pong", self.cluster);
+                        }
                         Message::Ping(data) => {
                             // Respond to ping with pong
                             write.send(Message::Pong(data)).await?;
                         }
-                        _ => {} // Ignore binary, pong
+                        _ => {} // Ignore binary
                     }
                 }
 
@@ -102,9 +180,44 @@ impl UpstreamConnection {
                     }
                     debug!("{} sent ping", self.cluster);
                 }
+
+                // Periodic health probe: force a reconnect if the upstream
+                // has gone quiet for too long even though the transport
+                // hasn't reported an error.
+                _ = health_check_interval.tick() => {
+                    if last_activity.elapsed() > STALE_CONNECTION_TIMEOUT {
+                        warn!(
+                            "{} upstream silent for {:?}, treating as dead",
+                            self.cluster, last_activity.elapsed()
+                        );
+                        break;
+                    }
+                }
             }
         }
 
         Ok(())
     }
-}
\ No newline at end of file
+
+    // Resend the full aggregate subscription set for this upstream's half
+    // of the feed (non-bar for firehose, bar for ms-aggregator) so clients
+    // who were already subscribed before the drop keep receiving data
+    // without having to resubscribe themselves.
+    async fn replay_subscriptions(&self, write: &mut UpstreamWrite) -> Result<()> {
+        let subs = self.subscriptions.lock().await;
+        let aggregate = match self.kind {
+            UpstreamKind::Firehose => subs.get_firehose_subscription(),
+            UpstreamKind::MsAggregator => subs.get_ms_aggregator_subscription(),
+        };
+        drop(subs);
+
+        if aggregate.is_empty() {
+            return Ok(());
+        }
+
+        info!("{} replaying subscriptions after reconnect: {}", self.cluster, aggregate);
+        let sub_msg = serde_json::to_string(&ClientMessage::Subscribe { params: aggregate })?;
+        write.send(Message::Text(sub_msg)).await?;
+        Ok(())
+    }
+}
@@ -1,66 +1,214 @@
-use std::collections::HashMap;
+use crate::metrics::Metrics;
+use crate::redis_backend::RedisSubscriber;
+use crate::types::{message_key, OutboundMessage};
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
-use tokio::sync::{mpsc, Mutex};
-use tracing::{debug, info};
+use tokio::sync::{mpsc, Mutex, Notify};
+use tracing::{debug, info, warn};
 use uuid::Uuid;
 
 pub type ClientId = Uuid;
 
-/// Simple broadcaster that sends all messages to all connected clients
+/// Filtering broadcaster: each client holds a set of subscription keys
+/// (`"T.AAPL"` or the wildcard `"T.*"`) and only messages matching one of
+/// them are forwarded to that client. Payloads are handed to clients as
+/// [`OutboundMessage`] rather than pre-serialized strings, so each client
+/// can encode to its own negotiated wire format.
 pub struct Broadcaster {
-    clients: Arc<Mutex<HashMap<ClientId, mpsc::Sender<String>>>>,
+    clients: Arc<Mutex<HashMap<ClientId, mpsc::Sender<OutboundMessage>>>>,
+    subscriptions: Arc<Mutex<HashMap<ClientId, HashSet<String>>>>,
+    /// Fired to tell a client's connection task to tear itself down after an eviction.
+    evict_notify: Arc<Mutex<HashMap<ClientId, Arc<Notify>>>>,
+    /// Consecutive full-channel drops per client, reset on a successful send.
+    consecutive_drops: Arc<Mutex<HashMap<ClientId, u32>>>,
+    slow_client_drop_threshold: u32,
+    metrics: Arc<Metrics>,
+    /// Set in `edge` mode: lazily subscribes to Redis channels as clients need them.
+    redis: Option<Arc<RedisSubscriber>>,
 }
 
 impl Broadcaster {
-    pub fn new() -> Self {
+    pub fn new(metrics: Arc<Metrics>, slow_client_drop_threshold: u32) -> Self {
         Self {
             clients: Arc::new(Mutex::new(HashMap::new())),
+            subscriptions: Arc::new(Mutex::new(HashMap::new())),
+            evict_notify: Arc::new(Mutex::new(HashMap::new())),
+            consecutive_drops: Arc::new(Mutex::new(HashMap::new())),
+            slow_client_drop_threshold,
+            metrics,
+            redis: None,
         }
     }
 
+    /// Source data from Redis pub/sub (populated by an `ingest` process)
+    /// instead of assuming an in-process upstream feeds this broadcaster.
+    pub fn with_redis(mut self, redis: Arc<RedisSubscriber>) -> Self {
+        self.redis = Some(redis);
+        self
+    }
+
+    /// The Polygon event type a subscription key belongs to, e.g. `"T.AAPL"` -> `"T"`.
+    fn event_type_of(key: &str) -> &str {
+        key.split_once('.').map(|(ev, _)| ev).unwrap_or(key)
+    }
+
     #[allow(dead_code)]
-    pub fn get_clients(&self) -> Arc<Mutex<HashMap<ClientId, mpsc::Sender<String>>>> {
+    pub fn get_clients(&self) -> Arc<Mutex<HashMap<ClientId, mpsc::Sender<OutboundMessage>>>> {
         self.clients.clone()
     }
 
-    pub async fn add_client(&self, client_id: ClientId, tx: mpsc::Sender<String>) {
+    /// Register a client. `evict_notify` is fired if this broadcaster later
+    /// decides to evict the client as a slow consumer, so its connection
+    /// task can tear itself down without waiting for a send to fail.
+    pub async fn add_client(
+        &self,
+        client_id: ClientId,
+        tx: mpsc::Sender<OutboundMessage>,
+        evict_notify: Arc<Notify>,
+    ) {
         let mut clients = self.clients.lock().await;
         clients.insert(client_id, tx);
+        self.subscriptions.lock().await.entry(client_id).or_default();
+        self.evict_notify.lock().await.insert(client_id, evict_notify);
+        self.metrics.client_connected();
         info!("Client {} added to broadcast list ({} total)", client_id, clients.len());
     }
 
     pub async fn remove_client(&self, client_id: ClientId) {
         let mut clients = self.clients.lock().await;
-        clients.remove(&client_id);
+        let existed = clients.remove(&client_id).is_some();
+        let removed_subs = self.subscriptions.lock().await.remove(&client_id);
+        self.evict_notify.lock().await.remove(&client_id);
+        self.consecutive_drops.lock().await.remove(&client_id);
+        if existed {
+            self.metrics.client_disconnected();
+        }
+        if let (Some(redis), Some(keys)) = (&self.redis, removed_subs) {
+            for key in keys {
+                redis.release(Self::event_type_of(&key)).await;
+            }
+        }
         info!("Client {} removed from broadcast list ({} remaining)", client_id, clients.len());
     }
 
+    /// Add subscription keys (e.g. `"T.AAPL"`, `"T.*"`) for a client.
+    pub async fn subscribe(&self, client_id: ClientId, keys: Vec<String>) {
+        let mut subs = self.subscriptions.lock().await;
+        let entry = subs.entry(client_id).or_default();
+        for key in keys {
+            debug!("Client {} subscribed to {}", client_id, key);
+            if entry.insert(key.clone()) {
+                if let Some(redis) = &self.redis {
+                    redis.acquire(Self::event_type_of(&key)).await;
+                }
+            }
+        }
+    }
+
+    /// Remove subscription keys for a client.
+    pub async fn unsubscribe(&self, client_id: ClientId, keys: Vec<String>) {
+        let mut subs = self.subscriptions.lock().await;
+        if let Some(entry) = subs.get_mut(&client_id) {
+            for key in keys {
+                debug!("Client {} unsubscribed from {}", client_id, key);
+                if entry.remove(&key) {
+                    if let Some(redis) = &self.redis {
+                        redis.release(Self::event_type_of(&key)).await;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Does `key` (e.g. `"T.AAPL"`) match anything in a client's subscription set?
+    fn matches(key: &str, subs: &HashSet<String>) -> bool {
+        if subs.contains(key) {
+            return true;
+        }
+        if let Some((ev, _sym)) = key.split_once('.') {
+            if subs.contains(&format!("{}.*", ev)) {
+                return true;
+            }
+        }
+        false
+    }
+
     pub async fn broadcast(&self, message: String) {
         let clients = self.clients.lock().await;
-        let client_count = clients.len();
-
-        if client_count == 0 {
+        if clients.is_empty() {
             return;
         }
 
-        debug!("Broadcasting message to {} clients", client_count);
+        // Messages that aren't a JSON array of Polygon events (e.g. status
+        // frames) can't be filtered by symbol, so forward them to everyone.
+        let Ok(serde_json::Value::Array(items)) = serde_json::from_str::<serde_json::Value>(&message)
+        else {
+            for tx in clients.values() {
+                let _ = tx.try_send(OutboundMessage::Raw(message.clone()));
+            }
+            return;
+        };
+
+        let subs = self.subscriptions.lock().await;
+        let mut per_client: HashMap<ClientId, Vec<serde_json::Value>> = HashMap::new();
+
+        for item in items {
+            let Some(key) = message_key(&item) else { continue };
+            for (client_id, client_subs) in subs.iter() {
+                if Self::matches(&key, client_subs) {
+                    per_client.entry(*client_id).or_default().push(item.clone());
+                }
+            }
+        }
+        drop(subs);
 
-        // Broadcast to all clients
-        // Use try_send for non-blocking, but log warnings for dropped messages
         let mut send_count = 0;
         let mut fail_count = 0;
-        for (client_id, tx) in clients.iter() {
-            match tx.try_send(message.clone()) {
-                Ok(_) => send_count += 1,
-                Err(e) => {
-                    fail_count += 1;
-                    // This will happen if client can't keep up with the data rate
-                    // Client should either increase buffer or filter more aggressively
-                    debug!("Client {} channel full, dropping message: {}", client_id, e);
+        let mut to_evict = Vec::new();
+
+        {
+            let mut drops = self.consecutive_drops.lock().await;
+            for (client_id, items) in per_client {
+                if items.is_empty() {
+                    continue;
+                }
+                let Some(tx) = clients.get(&client_id) else { continue };
+                let payload = OutboundMessage::Json(serde_json::Value::Array(items));
+
+                match tx.try_send(payload) {
+                    Ok(_) => {
+                        send_count += 1;
+                        self.metrics.record_broadcast();
+                        drops.remove(&client_id);
+                    }
+                    Err(_) => {
+                        fail_count += 1;
+                        self.metrics.record_dropped();
+                        let count = drops.entry(client_id).or_insert(0);
+                        *count += 1;
+                        debug!("Client {} channel full, {} consecutive drops", client_id, count);
+                        if *count >= self.slow_client_drop_threshold {
+                            to_evict.push(client_id);
+                        }
+                    }
                 }
             }
         }
 
+        drop(clients);
+
+        for client_id in to_evict {
+            warn!(
+                "Evicting slow client {} after {} consecutive drops",
+                client_id, self.slow_client_drop_threshold
+            );
+            if let Some(notify) = self.evict_notify.lock().await.get(&client_id) {
+                notify.notify_one();
+            }
+            self.metrics.record_slow_client_evicted();
+            self.remove_client(client_id).await;
+        }
+
         if fail_count > 0 && fail_count % 100 == 0 {
             info!("Broadcaster stats: {} sent, {} dropped", send_count, fail_count);
         }
@@ -1,11 +1,17 @@
 use crate::broadcaster::Broadcaster;
+use crate::types::{parse_subscription_keys, ClientFormat, ClientMessage, OutboundMessage};
 use anyhow::Result;
 use futures_util::{SinkExt, StreamExt};
 use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::mpsc;
-use tokio_tungstenite::{accept_async, tungstenite::Message};
+use tokio::sync::{mpsc, watch, Notify};
+use tokio_tungstenite::tungstenite::handshake::server::{Request, Response};
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+use tokio_tungstenite::{accept_hdr_async, tungstenite::Message};
 use tracing::{debug, error, info, warn};
 use uuid::Uuid;
 
@@ -13,14 +19,24 @@ pub struct ClientHandler {
     port: u16,
     broadcaster: Arc<Broadcaster>,
     auth_token: String,
+    shutdown_rx: watch::Receiver<bool>,
+    shutdown_grace: Duration,
 }
 
 impl ClientHandler {
-    pub fn new(port: u16, broadcaster: Arc<Broadcaster>, auth_token: String) -> Self {
+    pub fn new(
+        port: u16,
+        broadcaster: Arc<Broadcaster>,
+        auth_token: String,
+        shutdown_rx: watch::Receiver<bool>,
+        shutdown_grace: Duration,
+    ) -> Self {
         Self {
             port,
             broadcaster,
             auth_token,
+            shutdown_rx,
+            shutdown_grace,
         }
     }
 
@@ -29,15 +45,31 @@ impl ClientHandler {
         let listener = TcpListener::bind(&addr).await?;
         info!("Firehose proxy listening on {}", addr);
 
+        let mut shutdown_rx = self.shutdown_rx.clone();
         let handler = Arc::new(self);
 
-        while let Ok((stream, addr)) = listener.accept().await {
-            let handler = handler.clone();
-            tokio::spawn(async move {
-                if let Err(e) = handler.handle_client(stream, addr).await {
-                    error!("Client handler error: {}", e);
+        loop {
+            tokio::select! {
+                accepted = listener.accept() => {
+                    let (stream, addr) = match accepted {
+                        Ok(pair) => pair,
+                        Err(e) => {
+                            error!("Failed to accept client connection: {}", e);
+                            continue;
+                        }
+                    };
+                    let handler = handler.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = handler.handle_client(stream, addr).await {
+                            error!("Client handler error: {}", e);
+                        }
+                    });
+                }
+                _ = shutdown_rx.changed() => {
+                    info!("Client handler shutting down, no longer accepting new connections");
+                    break;
                 }
-            });
+            }
         }
 
         Ok(())
@@ -47,23 +79,54 @@ impl ClientHandler {
         let client_id = Uuid::new_v4();
         info!("Client {} connected from {}", client_id, addr);
 
-        let ws_stream = accept_async(stream).await?;
+        // `?format=msgpack` switches this client to binary MessagePack frames
+        // instead of JSON text; a client full of `T.*` trades is egress-heavy
+        // enough that this meaningfully cuts bandwidth. (permessage-deflate is
+        // not negotiated here: tokio-tungstenite's `Message` API doesn't expose
+        // the per-frame RSV1 bit, so there's no way to set it without dropping
+        // to raw frames — msgpack's denser encoding is the win we can take today.)
+        let wants_msgpack = Arc::new(AtomicBool::new(false));
+        let wants_msgpack_cb = wants_msgpack.clone();
+        let ws_stream = accept_hdr_async(stream, move |req: &Request, resp: Response| {
+            if let Some(query) = req.uri().query() {
+                if query.split('&').any(|kv| kv == "format=msgpack") {
+                    wants_msgpack_cb.store(true, AtomicOrdering::Relaxed);
+                }
+            }
+            Ok(resp)
+        })
+        .await?;
+        let format = if wants_msgpack.load(AtomicOrdering::Relaxed) {
+            ClientFormat::MsgPack
+        } else {
+            ClientFormat::Json
+        };
+        info!("Client {} negotiated format {:?}", client_id, format);
+
         let (mut ws_tx, mut ws_rx) = ws_stream.split();
 
         // Create channel for broadcasting to this client
         // Large buffer to handle bursts of market data
-        let (tx, mut rx) = mpsc::channel::<String>(100000);
+        let (tx, mut rx) = mpsc::channel::<OutboundMessage>(100000);
+
+        // Fired by the broadcaster if it evicts us as a slow consumer, so we
+        // don't just sit parked on `rx.recv()` forever.
+        let evicted = Arc::new(Notify::new());
 
         // Immediately add client to broadcast list (no auth required)
-        self.broadcaster.add_client(client_id, tx.clone()).await;
+        self.broadcaster.add_client(client_id, tx.clone(), evicted.clone()).await;
         info!("Client {} added to broadcast list", client_id);
 
+        let mut shutdown_rx = self.shutdown_rx.clone();
+        let mut close_reason: Option<(CloseCode, &'static str)> = None;
+
         // Combined task: forward broadcast messages and handle incoming messages
         loop {
             tokio::select! {
                 // Forward broadcast messages to client
                 Some(msg) = rx.recv() => {
-                    if ws_tx.send(Message::Text(msg)).await.is_err() {
+                    let Some(frame) = Self::encode(format, msg) else { continue };
+                    if ws_tx.send(frame).await.is_err() {
                         debug!("Client {} disconnected during send", client_id);
                         break;
                     }
@@ -82,22 +145,138 @@ impl ClientHandler {
                                 break;
                             }
                         }
+                        Ok(Message::Text(text)) => {
+                            if format == ClientFormat::MsgPack {
+                                warn!("Protocol error: client {} is in MsgPack mode but sent a text frame, disconnecting", client_id);
+                                break;
+                            }
+                            match serde_json::from_str::<ClientMessage>(&text) {
+                                Ok(parsed) => self.dispatch_client_message(client_id, parsed).await,
+                                Err(e) => debug!("Client {} sent unrecognized message: {} ({})", client_id, text, e),
+                            }
+                        }
+                        Ok(Message::Binary(data)) => {
+                            if format != ClientFormat::MsgPack {
+                                warn!("Protocol error: client {} is in JSON mode but sent a binary frame, disconnecting", client_id);
+                                break;
+                            }
+                            match rmp_serde::from_slice::<ClientMessage>(&data) {
+                                Ok(parsed) => self.dispatch_client_message(client_id, parsed).await,
+                                Err(e) => debug!("Client {} sent unrecognized MsgPack frame: {}", client_id, e),
+                            }
+                        }
                         Err(e) => {
                             warn!("WebSocket error for client {}: {}", client_id, e);
                             break;
                         }
                         _ => {
-                            // Ignore all other messages (text, pong, binary)
+                            // Ignore all other messages (pong)
                         }
                     }
                 }
+
+                // The broadcaster evicted us as a slow consumer
+                _ = evicted.notified() => {
+                    warn!("Client {} evicted as a slow consumer", client_id);
+                    close_reason = Some((CloseCode::Policy, "slow consumer"));
+                    break;
+                }
+
+                // Proxy is shutting down: drain whatever's left, then close
+                _ = shutdown_rx.changed() => {
+                    info!("Client {} draining before shutdown", client_id);
+                    self.drain(client_id, format, &mut rx, &mut ws_tx).await;
+                    close_reason = Some((CloseCode::Away, "server shutting down"));
+                    break;
+                }
             }
         }
 
-        // Cleanup
+        if let Some((code, reason)) = close_reason {
+            let _ = ws_tx
+                .send(Message::Close(Some(CloseFrame {
+                    code,
+                    reason: reason.into(),
+                })))
+                .await;
+        }
+
+        // Cleanup (a no-op if the broadcaster already removed us on eviction)
         self.broadcaster.remove_client(client_id).await;
         info!("Client {} handler terminated", client_id);
 
         Ok(())
     }
+
+    /// Flush whatever's already queued for this client within a bounded
+    /// grace period, instead of dropping it on the floor at shutdown.
+    async fn drain(
+        &self,
+        client_id: Uuid,
+        format: ClientFormat,
+        rx: &mut mpsc::Receiver<OutboundMessage>,
+        ws_tx: &mut (impl futures_util::Sink<Message> + Unpin),
+    ) {
+        let deadline = tokio::time::Instant::now() + self.shutdown_grace;
+        loop {
+            let remaining = deadline.saturating_duration_since(tokio::time::Instant::now());
+            if remaining.is_zero() {
+                warn!("Client {} drain grace period expired with messages still queued", client_id);
+                break;
+            }
+            tokio::select! {
+                msg = rx.recv() => {
+                    match msg {
+                        Some(msg) => {
+                            let Some(frame) = Self::encode(format, msg) else { continue };
+                            if ws_tx.send(frame).await.is_err() {
+                                break;
+                            }
+                        }
+                        None => break,
+                    }
+                }
+                _ = tokio::time::sleep(remaining) => break,
+            }
+        }
+    }
+
+    /// Encode a filtered [`OutboundMessage`] into the wire frame for a
+    /// client's negotiated format. Returns `None` if encoding fails (e.g. a
+    /// non-JSON raw status string reaching a MessagePack client).
+    fn encode(format: ClientFormat, msg: OutboundMessage) -> Option<Message> {
+        match format {
+            ClientFormat::Json => match msg {
+                OutboundMessage::Json(value) => serde_json::to_string(&value).ok().map(Message::Text),
+                OutboundMessage::Raw(text) => Some(Message::Text(text)),
+            },
+            ClientFormat::MsgPack => {
+                let value = match msg {
+                    OutboundMessage::Json(value) => value,
+                    OutboundMessage::Raw(text) => serde_json::from_str(&text).ok()?,
+                };
+                rmp_serde::to_vec(&value).ok().map(Message::Binary)
+            }
+        }
+    }
+
+    /// Apply a parsed `subscribe`/`unsubscribe`/`auth` frame to this client's
+    /// filter in the broadcaster, regardless of which wire format it arrived in.
+    async fn dispatch_client_message(&self, client_id: Uuid, message: ClientMessage) {
+        match message {
+            ClientMessage::Subscribe { params } => {
+                let keys = parse_subscription_keys(&params);
+                info!("Client {} subscribed to: {}", client_id, params);
+                self.broadcaster.subscribe(client_id, keys).await;
+            }
+            ClientMessage::Unsubscribe { params } => {
+                let keys = parse_subscription_keys(&params);
+                info!("Client {} unsubscribed from: {}", client_id, params);
+                self.broadcaster.unsubscribe(client_id, keys).await;
+            }
+            ClientMessage::Auth { .. } => {
+                debug!("Client {} sent auth (not required)", client_id);
+            }
+        }
+    }
 }
@@ -1,13 +1,55 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
 use std::env;
 
+/// Which half of the proxy this process runs, so a single Polygon connection
+/// can fan out to many horizontally-scaled edge processes via Redis instead
+/// of each one opening its own (rate-limited) upstream socket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProxyMode {
+    /// Single process: owns the upstream Polygon connection and serves clients directly.
+    InProcess,
+    /// Owns the upstream Polygon connection, publishes parsed messages to Redis, serves no clients.
+    Ingest,
+    /// Serves clients via `ClientHandler`/`Broadcaster`, sourcing data from Redis instead of Polygon.
+    Edge,
+}
+
+impl ProxyMode {
+    fn parse(raw: &str) -> Result<Self> {
+        match raw {
+            "in_process" => Ok(ProxyMode::InProcess),
+            "ingest" => Ok(ProxyMode::Ingest),
+            "edge" => Ok(ProxyMode::Edge),
+            other => bail!("PROXY_MODE must be one of in_process|ingest|edge, got {:?}", other),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Config {
-    pub polygon_api_key: String,
+    pub polygon_api_key: Option<String>,
     pub polygon_ws_url: String,
     pub proxy_port: u16,
     pub subscribe_data_types: Vec<String>,
     pub log_level: String,
+    /// Base delay for exponential reconnect backoff (ms)
+    pub reconnect_base_ms: u64,
+    /// Cap for exponential reconnect backoff (ms)
+    pub reconnect_max_ms: u64,
+    /// Treat the upstream socket as dead if no message arrives within this window (secs)
+    pub upstream_idle_timeout_secs: u64,
+    /// A connection must stay up at least this long before the backoff resets (secs)
+    pub reconnect_stable_after_secs: u64,
+    /// Port the Prometheus `/metrics` endpoint listens on
+    pub metrics_port: u16,
+    /// Consecutive full-channel drops before a client is evicted as a slow consumer
+    pub slow_client_drop_threshold: u32,
+    /// How long to let client tasks drain queued messages after a shutdown signal (ms)
+    pub shutdown_grace_ms: u64,
+    /// `in_process` (default), `ingest`, or `edge` — see [`ProxyMode`]
+    pub mode: ProxyMode,
+    /// Required when `mode` is `ingest` or `edge`
+    pub redis_url: Option<String>,
 }
 
 impl Config {
@@ -20,8 +62,10 @@ impl Config {
             .map(|s| s.trim().to_string())
             .collect();
 
-        Ok(Config {
-            polygon_api_key: env::var("POLYGON_API_KEY")?,
+        let mode = ProxyMode::parse(&env::var("PROXY_MODE").unwrap_or_else(|_| "in_process".to_string()))?;
+
+        let config = Config {
+            polygon_api_key: env::var("POLYGON_API_KEY").ok(),
             polygon_ws_url: env::var("POLYGON_WS_URL")
                 .unwrap_or_else(|_| "wss://socket.polygon.io/stocks".to_string()),
             proxy_port: env::var("PROXY_PORT")
@@ -30,7 +74,48 @@ impl Config {
             subscribe_data_types: data_types,
             log_level: env::var("LOG_LEVEL")
                 .unwrap_or_else(|_| "info".to_string()),
-        })
+            reconnect_base_ms: env::var("RECONNECT_BASE_MS")
+                .unwrap_or_else(|_| "500".to_string())
+                .parse()?,
+            reconnect_max_ms: env::var("RECONNECT_MAX_MS")
+                .unwrap_or_else(|_| "30000".to_string())
+                .parse()?,
+            upstream_idle_timeout_secs: env::var("UPSTREAM_IDLE_TIMEOUT_SECS")
+                .unwrap_or_else(|_| "30".to_string())
+                .parse()?,
+            reconnect_stable_after_secs: env::var("RECONNECT_STABLE_AFTER_SECS")
+                .unwrap_or_else(|_| "60".to_string())
+                .parse()?,
+            metrics_port: env::var("METRICS_PORT")
+                .unwrap_or_else(|_| "9090".to_string())
+                .parse()?,
+            slow_client_drop_threshold: env::var("SLOW_CLIENT_DROP_THRESHOLD")
+                .unwrap_or_else(|_| "50".to_string())
+                .parse()?,
+            shutdown_grace_ms: env::var("SHUTDOWN_GRACE_MS")
+                .unwrap_or_else(|_| "5000".to_string())
+                .parse()?,
+            mode,
+            redis_url: env::var("REDIS_URL").ok(),
+        };
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<()> {
+        match self.mode {
+            ProxyMode::InProcess | ProxyMode::Ingest => {
+                if self.polygon_api_key.is_none() {
+                    bail!("POLYGON_API_KEY must be set in {:?} mode", self.mode);
+                }
+            }
+            ProxyMode::Edge => {}
+        }
+        if matches!(self.mode, ProxyMode::Ingest | ProxyMode::Edge) && self.redis_url.is_none() {
+            bail!("REDIS_URL must be set in {:?} mode", self.mode);
+        }
+        Ok(())
     }
 
     pub fn get_subscription_string(&self) -> String {
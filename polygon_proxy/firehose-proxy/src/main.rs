@@ -1,15 +1,21 @@
 mod broadcaster;
 mod client_handler;
 mod config;
+mod metrics;
+mod redis_backend;
+mod shutdown;
 mod types;
 mod upstream;
 
 use anyhow::Result;
 use broadcaster::Broadcaster;
 use client_handler::ClientHandler;
-use config::Config;
+use config::{Config, ProxyMode};
+use metrics::Metrics;
+use redis_backend::{RedisPublisher, RedisSubscriber};
 use std::sync::Arc;
-use tokio::sync::mpsc;
+use std::time::Duration;
+use tokio::sync::{mpsc, watch};
 use tracing::info;
 use upstream::PolygonConnection;
 
@@ -23,34 +29,119 @@ async fn main() -> Result<()> {
         .with_env_filter(format!("firehose_proxy={}", config.log_level))
         .init();
 
-    info!("Starting Polygon Firehose Proxy");
+    info!("Starting Polygon Firehose Proxy in {:?} mode", config.mode);
     info!("Configured data types: {:?}", config.subscribe_data_types);
     info!("Proxy port: {}", config.proxy_port);
 
-    // Create broadcaster
-    let broadcaster = Arc::new(Broadcaster::new());
+    // Create metrics and start the /metrics scrape endpoint
+    let metrics = Metrics::new();
+    let metrics_for_server = metrics.clone();
+    let metrics_port = config.metrics_port;
+    tokio::spawn(async move {
+        metrics::serve(metrics_for_server, metrics_port).await;
+    });
+
+    // Wire up SIGINT/SIGTERM so every loop below can drain instead of dying mid-flight
+    let shutdown_rx = shutdown::listen();
+
+    match config.mode {
+        ProxyMode::InProcess => run_in_process(config, metrics, shutdown_rx).await,
+        ProxyMode::Ingest => run_ingest(config, metrics, shutdown_rx).await,
+        ProxyMode::Edge => run_edge(config, metrics, shutdown_rx).await,
+    }
+}
+
+/// Single process: the upstream Polygon connection feeds the broadcaster directly.
+async fn run_in_process(config: Config, metrics: Arc<Metrics>, shutdown_rx: watch::Receiver<bool>) -> Result<()> {
+    let broadcaster = Arc::new(Broadcaster::new(metrics.clone(), config.slow_client_drop_threshold));
+
+    // Large buffer to handle high-frequency market data bursts
+    let (broadcast_tx, mut broadcast_rx) = mpsc::channel::<String>(100000);
+
+    let polygon_conn =
+        PolygonConnection::new(config.clone(), broadcast_tx, metrics.clone(), shutdown_rx.clone());
+    tokio::spawn(async move {
+        polygon_conn.run().await;
+    });
+
+    spawn_client_handler(&config, broadcaster.clone(), shutdown_rx);
+
+    info!("Broadcast loop started");
+    run_broadcast_loop(&broadcaster, &mut broadcast_rx).await;
+    Ok(())
+}
+
+/// Owns the upstream Polygon connection; republishes to Redis instead of serving clients.
+async fn run_ingest(config: Config, metrics: Arc<Metrics>, shutdown_rx: watch::Receiver<bool>) -> Result<()> {
+    let redis_url = config
+        .redis_url
+        .clone()
+        .expect("REDIS_URL validated present for ingest mode");
+    let publisher = RedisPublisher::connect(&redis_url)?;
 
-    // Create channel for upstream messages
     // Large buffer to handle high-frequency market data bursts
     let (broadcast_tx, mut broadcast_rx) = mpsc::channel::<String>(100000);
 
-    // Start upstream Polygon connection
-    let polygon_conn = PolygonConnection::new(config.clone(), broadcast_tx);
+    let polygon_conn =
+        PolygonConnection::new(config.clone(), broadcast_tx, metrics.clone(), shutdown_rx);
     tokio::spawn(async move {
         polygon_conn.run().await;
     });
 
-    // Start client handler
+    info!("Ingest loop started, republishing to Redis at {}", redis_url);
+    while let Some(message) = broadcast_rx.recv().await {
+        if let Err(e) = publisher.publish_batch(&message).await {
+            tracing::warn!("Failed to publish batch to Redis: {}", e);
+        }
+    }
+
+    info!("Ingest loop ended, shutting down");
+    Ok(())
+}
+
+/// Serves clients via `ClientHandler`/`Broadcaster`, sourcing data from Redis
+/// instead of an in-process upstream connection.
+async fn run_edge(config: Config, metrics: Arc<Metrics>, shutdown_rx: watch::Receiver<bool>) -> Result<()> {
+    let redis_url = config
+        .redis_url
+        .clone()
+        .expect("REDIS_URL validated present for edge mode");
+
+    // Large buffer to handle high-frequency market data bursts
+    let (broadcast_tx, mut broadcast_rx) = mpsc::channel::<String>(100000);
+    let redis = Arc::new(RedisSubscriber::new(redis_url.clone(), broadcast_tx));
+
+    let broadcaster = Arc::new(
+        Broadcaster::new(metrics.clone(), config.slow_client_drop_threshold).with_redis(redis),
+    );
+
+    spawn_client_handler(&config, broadcaster.clone(), shutdown_rx);
+
+    info!("Edge broadcast loop started, sourcing from Redis at {}", redis_url);
+    run_broadcast_loop(&broadcaster, &mut broadcast_rx).await;
+    Ok(())
+}
+
+fn spawn_client_handler(config: &Config, broadcaster: Arc<Broadcaster>, shutdown_rx: watch::Receiver<bool>) {
     let auth_token = "firehose-token-12345".to_string(); // Simple token for demo
-    let client_handler = ClientHandler::new(config.proxy_port, broadcaster.clone(), auth_token);
+    let shutdown_grace = Duration::from_millis(config.shutdown_grace_ms);
+    let client_handler = ClientHandler::new(
+        config.proxy_port,
+        broadcaster,
+        auth_token,
+        shutdown_rx,
+        shutdown_grace,
+    );
     tokio::spawn(async move {
         if let Err(e) = client_handler.run().await {
             tracing::error!("Client handler error: {}", e);
         }
     });
+}
 
-    // Broadcast loop: forward all upstream messages to all clients
-    info!("Broadcast loop started");
+/// Forward every message arriving on `broadcast_rx` to `broadcaster`, until
+/// the sending half is dropped (upstream/Redis subscriber shut down).
+async fn run_broadcast_loop(broadcaster: &Arc<Broadcaster>, broadcast_rx: &mut mpsc::Receiver<String>) {
     let mut msg_count = 0;
     while let Some(message) = broadcast_rx.recv().await {
         msg_count += 1;
@@ -59,6 +150,5 @@ async fn main() -> Result<()> {
         }
         broadcaster.broadcast(message).await;
     }
-
-    Ok(())
+    info!("Broadcast loop ended, shutting down");
 }
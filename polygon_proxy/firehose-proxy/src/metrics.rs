@@ -0,0 +1,146 @@
+use dashmap::DashMap;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpListener;
+use tracing::{error, info};
+
+/// Runtime counters for the firehose proxy, exposed in Prometheus text
+/// exposition format over `/metrics`.
+#[derive(Default)]
+pub struct Metrics {
+    upstream_messages_total: DashMap<String, AtomicU64>,
+    messages_broadcast_total: AtomicU64,
+    connected_clients: AtomicI64,
+    upstream_reconnects_total: AtomicU64,
+    messages_dropped_total: AtomicU64,
+    slow_clients_evicted_total: AtomicU64,
+}
+
+impl Metrics {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    pub fn record_upstream_message(&self, event_type: &str) {
+        self.upstream_messages_total
+            .entry(event_type.to_string())
+            .or_insert_with(|| AtomicU64::new(0))
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_broadcast(&self) {
+        self.messages_broadcast_total.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn client_connected(&self) {
+        self.connected_clients.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn client_disconnected(&self) {
+        self.connected_clients.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn record_reconnect(&self) {
+        self.upstream_reconnects_total.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_dropped(&self) {
+        self.messages_dropped_total.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_slow_client_evicted(&self) {
+        self.slow_clients_evicted_total.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Render all counters/gauges in Prometheus text exposition format.
+    fn render(&self) -> String {
+        let mut out = String::new();
+
+        out.push_str("# HELP upstream_messages_total Messages received from Polygon, labeled by event type\n");
+        out.push_str("# TYPE upstream_messages_total counter\n");
+        for entry in self.upstream_messages_total.iter() {
+            out.push_str(&format!(
+                "upstream_messages_total{{event_type=\"{}\"}} {}\n",
+                entry.key(),
+                entry.value().load(Ordering::Relaxed)
+            ));
+        }
+
+        out.push_str("# HELP messages_broadcast_total Messages queued to client channels, counted once per receiving client\n");
+        out.push_str("# TYPE messages_broadcast_total counter\n");
+        out.push_str(&format!(
+            "messages_broadcast_total {}\n",
+            self.messages_broadcast_total.load(Ordering::Relaxed)
+        ));
+
+        out.push_str("# HELP connected_clients Currently connected WebSocket clients\n");
+        out.push_str("# TYPE connected_clients gauge\n");
+        out.push_str(&format!(
+            "connected_clients {}\n",
+            self.connected_clients.load(Ordering::Relaxed)
+        ));
+
+        out.push_str("# HELP upstream_reconnects_total Reconnect attempts to the Polygon upstream\n");
+        out.push_str("# TYPE upstream_reconnects_total counter\n");
+        out.push_str(&format!(
+            "upstream_reconnects_total {}\n",
+            self.upstream_reconnects_total.load(Ordering::Relaxed)
+        ));
+
+        out.push_str("# HELP messages_dropped_total Messages dropped because a client's channel was full\n");
+        out.push_str("# TYPE messages_dropped_total counter\n");
+        out.push_str(&format!(
+            "messages_dropped_total {}\n",
+            self.messages_dropped_total.load(Ordering::Relaxed)
+        ));
+
+        out.push_str("# HELP slow_clients_evicted_total Clients disconnected for exceeding the slow-consumer drop threshold\n");
+        out.push_str("# TYPE slow_clients_evicted_total counter\n");
+        out.push_str(&format!(
+            "slow_clients_evicted_total {}\n",
+            self.slow_clients_evicted_total.load(Ordering::Relaxed)
+        ));
+
+        out
+    }
+}
+
+/// Serve `/metrics` on `port` until the process exits.
+pub async fn serve(metrics: Arc<Metrics>, port: u16) {
+    let addr = format!("0.0.0.0:{}", port);
+    let listener = match TcpListener::bind(&addr).await {
+        Ok(l) => l,
+        Err(e) => {
+            error!("Failed to bind metrics listener on {}: {}", addr, e);
+            return;
+        }
+    };
+    info!("Metrics endpoint listening on {}/metrics", addr);
+
+    loop {
+        let (mut stream, _) = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                error!("Metrics listener accept error: {}", e);
+                continue;
+            }
+        };
+
+        let metrics = metrics.clone();
+        tokio::spawn(async move {
+            // We only serve GET /metrics; drain and ignore the request itself.
+            let mut buf = [0u8; 1024];
+            let _ = tokio::io::AsyncReadExt::read(&mut stream, &mut buf).await;
+
+            let body = metrics.render();
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            let _ = stream.write_all(response.as_bytes()).await;
+            let _ = stream.shutdown().await;
+        });
+    }
+}
@@ -0,0 +1,144 @@
+use anyhow::Result;
+use futures_util::StreamExt;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, Mutex};
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+/// Redis channel a given Polygon event type is published/subscribed under, e.g. `"T"` -> `"poly:T"`.
+fn channel_for_event_type(event_type: &str) -> String {
+    format!("poly:{}", event_type)
+}
+
+/// Runs in an `ingest` process: owns the upstream Polygon connection and
+/// republishes each batch of events to Redis, split per event type, so any
+/// number of `edge` processes can fan out from Redis instead of each opening
+/// their own upstream socket.
+pub struct RedisPublisher {
+    client: redis::Client,
+}
+
+impl RedisPublisher {
+    pub fn connect(redis_url: &str) -> Result<Self> {
+        Ok(Self {
+            client: redis::Client::open(redis_url)?,
+        })
+    }
+
+    /// Split a raw Polygon batch by event type and publish each group to its
+    /// own channel, so edge instances only pay for the channels they need.
+    pub async fn publish_batch(&self, raw: &str) -> Result<()> {
+        let Ok(serde_json::Value::Array(items)) = serde_json::from_str::<serde_json::Value>(raw)
+        else {
+            return Ok(());
+        };
+
+        let mut by_event_type: HashMap<String, Vec<serde_json::Value>> = HashMap::new();
+        for item in items {
+            if let Some(ev) = item.get("ev").and_then(|v| v.as_str()) {
+                by_event_type.entry(ev.to_string()).or_default().push(item);
+            }
+        }
+
+        let mut conn = self.client.get_multiplexed_async_connection().await?;
+        for (event_type, items) in by_event_type {
+            let payload = serde_json::to_string(&items)?;
+            let channel = channel_for_event_type(&event_type);
+            let _: () = redis::cmd("PUBLISH")
+                .arg(&channel)
+                .arg(&payload)
+                .query_async(&mut conn)
+                .await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Runs in an `edge` process: lazily subscribes to Redis channels as clients
+/// ask for them, and unsubscribes once nothing needs them anymore. Each
+/// channel gets its own connection and task, so adding/dropping one never
+/// disturbs the others, and fan-in scales with active client subscriptions
+/// rather than total market volume.
+pub struct RedisSubscriber {
+    redis_url: String,
+    broadcast_tx: mpsc::Sender<String>,
+    /// event type -> (subscriber task handle, number of client subscriptions referencing it)
+    channels: Arc<Mutex<HashMap<String, (JoinHandle<()>, u32)>>>,
+}
+
+impl RedisSubscriber {
+    pub fn new(redis_url: String, broadcast_tx: mpsc::Sender<String>) -> Self {
+        Self {
+            redis_url,
+            broadcast_tx,
+            channels: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Take a reference on `event_type` (e.g. `"T"`), subscribing to its Redis
+    /// channel if this is the first client that needs it.
+    pub async fn acquire(&self, event_type: &str) {
+        let mut channels = self.channels.lock().await;
+        if let Some((_, refcount)) = channels.get_mut(event_type) {
+            *refcount += 1;
+            return;
+        }
+
+        let redis_url = self.redis_url.clone();
+        let broadcast_tx = self.broadcast_tx.clone();
+        let channel = channel_for_event_type(event_type);
+        let event_type_owned = event_type.to_string();
+        let handle = tokio::spawn(async move {
+            loop {
+                match Self::run_channel(&redis_url, &channel, broadcast_tx.clone()).await {
+                    Ok(_) => info!("Redis subscription to {} ended", channel),
+                    Err(e) => warn!("Redis subscription to {} failed: {}", channel, e),
+                }
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        });
+
+        info!("Subscribing to Redis channel for event type {}", event_type_owned);
+        channels.insert(event_type_owned, (handle, 1));
+    }
+
+    /// Release a reference on `event_type`, unsubscribing once no client needs it anymore.
+    pub async fn release(&self, event_type: &str) {
+        let mut channels = self.channels.lock().await;
+        let Some((_, refcount)) = channels.get_mut(event_type) else {
+            return;
+        };
+        *refcount = refcount.saturating_sub(1);
+        if *refcount == 0 {
+            if let Some((handle, _)) = channels.remove(event_type) {
+                info!("Unsubscribing from Redis channel for event type {}", event_type);
+                handle.abort();
+            }
+        }
+    }
+
+    async fn run_channel(redis_url: &str, channel: &str, broadcast_tx: mpsc::Sender<String>) -> Result<()> {
+        let client = redis::Client::open(redis_url)?;
+        let mut pubsub = client.get_async_pubsub().await?;
+        pubsub.subscribe(channel).await?;
+
+        let mut stream = pubsub.into_on_message();
+        while let Some(msg) = stream.next().await {
+            let payload: String = match msg.get_payload() {
+                Ok(p) => p,
+                Err(e) => {
+                    error!("Malformed Redis pub/sub payload on {}: {}", channel, e);
+                    continue;
+                }
+            };
+            if broadcast_tx.send(payload).await.is_err() {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -14,14 +14,35 @@ pub struct PolygonSubscribe {
     pub params: String,
 }
 
-// Client messages (simplified - only auth and subscribe to *)
+// Client messages
 #[derive(Debug, Deserialize)]
 #[serde(tag = "action")]
 pub enum ClientMessage {
     #[serde(rename = "auth")]
     Auth { token: String },
     #[serde(rename = "subscribe")]
-    Subscribe,
+    Subscribe { params: String },
+    #[serde(rename = "unsubscribe")]
+    Unsubscribe { params: String },
+}
+
+/// Split a subscribe/unsubscribe `params` string ("T.AAPL,T.MSFT") into its
+/// individual `EV.SYM` keys.
+pub fn parse_subscription_keys(params: &str) -> Vec<String> {
+    params
+        .split(',')
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_string())
+        .collect()
+}
+
+/// Extract the `EV.SYM` subscription key a Polygon message belongs to, e.g.
+/// a trade for AAPL becomes `"T.AAPL"`.
+pub fn message_key(value: &serde_json::Value) -> Option<String> {
+    let ev = value.get("ev").and_then(|v| v.as_str())?;
+    let sym = value.get("sym").and_then(|v| v.as_str())?;
+    Some(format!("{}.{}", ev, sym))
 }
 
 // Status message to clients
@@ -30,3 +51,23 @@ pub struct StatusMessage {
     pub status: String,
     pub message: String,
 }
+
+/// Wire format a client negotiated at connect time, via `?format=msgpack`
+/// on the WebSocket URL (default is plain JSON text frames).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientFormat {
+    Json,
+    MsgPack,
+}
+
+/// A message queued for delivery to one client, already filtered down to
+/// that client's subscriptions. Serialization is deferred to the client's
+/// own task so each client can encode to its own negotiated `ClientFormat`.
+#[derive(Debug, Clone)]
+pub enum OutboundMessage {
+    /// A batch of Polygon events (or any other JSON value) to encode per client.
+    Json(serde_json::Value),
+    /// An already-serialized JSON string, forwarded to JSON clients verbatim
+    /// and re-parsed for MessagePack clients.
+    Raw(String),
+}
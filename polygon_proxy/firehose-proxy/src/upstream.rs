@@ -1,31 +1,85 @@
 use crate::config::Config;
-use crate::types::{PolygonAuth, PolygonSubscribe};
+use crate::metrics::Metrics;
+use crate::types::{message_key, PolygonAuth, PolygonSubscribe};
 use anyhow::Result;
 use futures_util::{SinkExt, StreamExt};
-use tokio::sync::mpsc;
-use tokio::time::{interval, Duration};
+use std::collections::HashSet;
+use std::sync::Arc;
+use tokio::sync::{mpsc, watch};
+use tokio::time::{interval, Duration, Instant};
 use tokio_tungstenite::{connect_async, tungstenite::Message};
 use tracing::{debug, error, info, warn};
 
 pub struct PolygonConnection {
     config: Config,
     broadcast_tx: mpsc::Sender<String>,
+    metrics: Arc<Metrics>,
+    /// Subscription strings currently active upstream, replayed after every reconnect.
+    active_subscriptions: HashSet<String>,
+    shutdown_rx: watch::Receiver<bool>,
 }
 
 impl PolygonConnection {
-    pub fn new(config: Config, broadcast_tx: mpsc::Sender<String>) -> Self {
+    pub fn new(
+        config: Config,
+        broadcast_tx: mpsc::Sender<String>,
+        metrics: Arc<Metrics>,
+        shutdown_rx: watch::Receiver<bool>,
+    ) -> Self {
+        let active_subscriptions = config
+            .get_subscription_string()
+            .split(',')
+            .filter(|s| !s.is_empty())
+            .map(|s| s.to_string())
+            .collect();
+
         Self {
             config,
             broadcast_tx,
+            metrics,
+            active_subscriptions,
+            shutdown_rx,
         }
     }
 
     pub async fn run(mut self) {
+        let mut attempt: u32 = 0;
+
         loop {
-            if let Err(e) = self.connect_and_stream().await {
-                error!("Polygon connection error: {}", e);
-                tokio::time::sleep(Duration::from_secs(5)).await;
-                info!("Reconnecting to Polygon...");
+            let connected_at = Instant::now();
+
+            match self.connect_and_stream().await {
+                Ok(_) => info!("Polygon connection closed normally"),
+                Err(e) => error!("Polygon connection error: {}", e),
+            }
+
+            if *self.shutdown_rx.borrow() {
+                info!("Shutdown in progress, not reconnecting to Polygon");
+                break;
+            }
+
+            self.metrics.record_reconnect();
+
+            if connected_at.elapsed() >= Duration::from_secs(self.config.reconnect_stable_after_secs)
+            {
+                attempt = 0;
+            } else {
+                attempt += 1;
+            }
+
+            let delay = backoff_with_jitter(
+                attempt,
+                self.config.reconnect_base_ms,
+                self.config.reconnect_max_ms,
+            );
+            info!("Reconnecting to Polygon in {:?} (attempt {})", delay, attempt);
+
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                _ = self.shutdown_rx.changed() => {
+                    info!("Shutdown received while waiting to reconnect to Polygon");
+                    break;
+                }
             }
         }
     }
@@ -41,7 +95,11 @@ impl PolygonConnection {
         // Send authentication
         let auth = PolygonAuth {
             action: "auth".to_string(),
-            params: self.config.polygon_api_key.clone(),
+            params: self
+                .config
+                .polygon_api_key
+                .clone()
+                .expect("POLYGON_API_KEY validated present for this mode"),
         };
         let auth_msg = serde_json::to_string(&auth)?;
         write.send(Message::Text(auth_msg)).await?;
@@ -57,8 +115,10 @@ impl PolygonConnection {
             }
         }
 
-        // Subscribe to configured data types with wildcard
-        let subscription = self.config.get_subscription_string();
+        // Replay the full subscription set (config-driven today, but kept as
+        // explicit state so a future per-client upstream subscribe protocol
+        // survives reconnects for free).
+        let subscription = self.active_subscriptions.iter().cloned().collect::<Vec<_>>().join(",");
         info!("Subscribing to: {}", subscription);
 
         let subscribe = PolygonSubscribe {
@@ -72,14 +132,18 @@ impl PolygonConnection {
 
         // Ping interval to keep connection alive
         let mut ping_interval = interval(Duration::from_secs(30));
+        let idle_timeout = Duration::from_secs(self.config.upstream_idle_timeout_secs);
+        let mut last_message_at = Instant::now();
 
         // Stream messages
         loop {
             tokio::select! {
                 Some(msg) = read.next() => {
+                    last_message_at = Instant::now();
                     match msg? {
                         Message::Text(text) => {
                             info!("Received message from Polygon: {}", &text[..text.len().min(200)]);
+                            self.record_event_types(&text);
                             // Broadcast to all connected clients
                             if let Err(e) = self.broadcast_tx.send(text).await {
                                 warn!("Failed to broadcast message: {}", e);
@@ -102,9 +166,49 @@ impl PolygonConnection {
                     }
                     debug!("Sent ping to Polygon");
                 }
+                _ = tokio::time::sleep(idle_timeout.saturating_sub(last_message_at.elapsed())) => {
+                    if last_message_at.elapsed() >= idle_timeout {
+                        warn!(
+                            "No data from Polygon in {:?}, treating socket as wedged",
+                            idle_timeout
+                        );
+                        return Err(anyhow::anyhow!("Upstream socket idle for {:?}", idle_timeout));
+                    }
+                }
+                _ = self.shutdown_rx.changed() => {
+                    info!("Shutdown received, closing Polygon connection");
+                    let _ = write.send(Message::Close(None)).await;
+                    break;
+                }
             }
         }
 
         Ok(())
     }
+
+    /// Count each message in a Polygon batch against its `ev` label.
+    fn record_event_types(&self, text: &str) {
+        if let Ok(serde_json::Value::Array(items)) = serde_json::from_str::<serde_json::Value>(text)
+        {
+            for item in &items {
+                if let Some(key) = message_key(item) {
+                    if let Some((ev, _)) = key.split_once('.') {
+                        self.metrics.record_upstream_message(ev);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// `delay = min(cap, base * 2^attempt)`, then uniform jitter in `[0, delay]`.
+fn backoff_with_jitter(attempt: u32, base_ms: u64, cap_ms: u64) -> Duration {
+    let exp = base_ms.saturating_mul(2u64.saturating_pow(attempt));
+    let delay_ms = exp.min(cap_ms);
+    let jittered = if delay_ms == 0 {
+        0
+    } else {
+        rand::random::<u64>() % (delay_ms + 1)
+    };
+    Duration::from_millis(jittered)
 }
@@ -1,61 +1,58 @@
 use crate::types::{MsBar, PolygonTrade};
+use std::collections::VecDeque;
 use std::time::{SystemTime, UNIX_EPOCH};
 
-/// Aggregates trades into OHLCV bars
+/// Always keep at least the current window plus one prior window buffered,
+/// even if a caller asks for a smaller ring.
+const MIN_RING_SIZE: usize = 2;
+
+/// One in-progress OHLCV window, identified by its start timestamp.
 #[derive(Debug, Clone)]
-pub struct BarAggregator {
-    symbol: String,
-    interval_ms: u64,
+struct Window {
+    window_start: u64,
+    window_end: u64,
     open: Option<f64>,
+    open_time: u64,
     high: Option<f64>,
     low: Option<f64>,
     close: Option<f64>,
+    close_time: u64,
     volume: u64,
     num_trades: u32,
-    window_start: u64,
-    window_end: u64,
-    last_trade_time: u64,
 }
 
-impl BarAggregator {
-    pub fn new(symbol: String, interval_ms: u64) -> Self {
-        let now = current_timestamp_ms();
-        let window_start = (now / interval_ms) * interval_ms;
-        let window_end = window_start + interval_ms;
-
+impl Window {
+    fn new(window_start: u64, window_end: u64) -> Self {
         Self {
-            symbol,
-            interval_ms,
+            window_start,
+            window_end,
             open: None,
+            open_time: u64::MAX,
             high: None,
             low: None,
             close: None,
+            close_time: 0,
             volume: 0,
             num_trades: 0,
-            window_start,
-            window_end,
-            last_trade_time: 0,
         }
     }
 
-    /// Add a trade to the current bar
-    pub fn add_trade(&mut self, trade: &PolygonTrade) {
-        let trade_time = trade.timestamp;
-
-        // If trade is before current window, ignore it (late data)
-        if trade_time < self.window_start {
-            return;
-        }
-
-        // If trade is in a future window, we need to handle window boundaries
-        // For now, we'll just accumulate within the current window
-        if trade_time >= self.window_end {
-            return;
-        }
+    fn has_data(&self) -> bool {
+        self.open.is_some()
+    }
 
-        // Update OHLCV
-        if self.open.is_none() {
+    fn add_trade(&mut self, trade: &PolygonTrade) {
+        // `open` has to track the earliest trade timestamp seen so far (not
+        // just the first one applied), so a late-arriving trade from
+        // earlier in the window can still correct it. `close` mirrors this
+        // with the latest timestamp seen.
+        if trade.timestamp <= self.open_time {
             self.open = Some(trade.price);
+            self.open_time = trade.timestamp;
+        }
+        if trade.timestamp >= self.close_time {
+            self.close = Some(trade.price);
+            self.close_time = trade.timestamp;
         }
 
         self.high = Some(
@@ -63,76 +60,180 @@ impl BarAggregator {
                 .map(|h| h.max(trade.price))
                 .unwrap_or(trade.price),
         );
-
         self.low = Some(
             self.low
                 .map(|l| l.min(trade.price))
                 .unwrap_or(trade.price),
         );
-
-        self.close = Some(trade.price);
         self.volume += trade.size;
         self.num_trades += 1;
-        self.last_trade_time = trade_time;
     }
+}
+
+/// Aggregates trades into OHLCV bars.
+///
+/// Keeps a small ring of buffered windows - the current one plus a handful
+/// of prior ones - so a burst of trades spanning a window boundary, or a
+/// trade that simply arrives out of order, still lands in the right window
+/// instead of being silently dropped. Only a trade that fits no buffered
+/// window - older than all of them, or too far ahead of the newest - is
+/// counted as a drop.
+#[derive(Debug, Clone)]
+pub struct BarAggregator {
+    symbol: String,
+    interval_ms: u64,
+    // Oldest first; bounded to `ring_size` entries.
+    windows: VecDeque<Window>,
+    ring_size: usize,
+    dropped_late_trades: u64,
+}
 
-    /// Check if the current bar is ready to be emitted
-    /// Returns true if current time is past window_end + delay
-    pub fn is_ready(&self, delay_ms: u64) -> bool {
+impl BarAggregator {
+    /// `ring_size` is the total number of windows kept around, the current
+    /// one included, for late/out-of-order trades (from `Config::bar_ring_size`).
+    pub fn new(symbol: String, interval_ms: u64, ring_size: usize) -> Self {
         let now = current_timestamp_ms();
-        now >= self.window_end + delay_ms
+        let window_start = (now / interval_ms) * interval_ms;
+        let window_end = window_start + interval_ms;
+
+        let mut windows = VecDeque::new();
+        windows.push_back(Window::new(window_start, window_end));
+
+        Self {
+            symbol,
+            interval_ms,
+            windows,
+            ring_size: ring_size.max(MIN_RING_SIZE),
+            dropped_late_trades: 0,
+        }
+    }
+
+    /// Add a trade to its matching buffered window. Returns `true` if the
+    /// trade was dropped instead: older than every buffered window, or too
+    /// far ahead of the newest one.
+    pub fn add_trade(&mut self, trade: &PolygonTrade) -> bool {
+        let trade_window_start = (trade.timestamp / self.interval_ms) * self.interval_ms;
+
+        if let Some(oldest) = self.windows.front() {
+            if trade_window_start < oldest.window_start {
+                self.dropped_late_trades += 1;
+                return true;
+            }
+        }
+
+        if let Some(newest) = self.windows.back() {
+            // A trade whose timestamp is further ahead than the ring can
+            // ever hold would make `grow_to` push an unbounded number of
+            // windows before this trade lands - e.g. a corrupted or
+            // unit-mismatched (seconds vs. millis) upstream timestamp. That
+            // would stall bar aggregation for every other symbol sharing
+            // this task, so treat it as a drop instead of growing toward it.
+            let max_future_start =
+                newest.window_start + (self.ring_size as u64) * self.interval_ms;
+            if trade_window_start > max_future_start {
+                self.dropped_late_trades += 1;
+                return true;
+            }
+        }
+
+        self.grow_to(trade_window_start);
+
+        match self
+            .windows
+            .iter_mut()
+            .find(|w| w.window_start == trade_window_start)
+        {
+            Some(window) => {
+                window.add_trade(trade);
+                false
+            }
+            None => {
+                // Evicted from the ring by `grow_to` making room for newer
+                // windows before this trade could land.
+                self.dropped_late_trades += 1;
+                true
+            }
+        }
+    }
+
+    /// Extend the ring forward so its newest window covers `target_start`,
+    /// evicting the oldest buffered window once `ring_size` is exceeded.
+    fn grow_to(&mut self, target_start: u64) {
+        if self.windows.is_empty() {
+            self.windows
+                .push_back(Window::new(target_start, target_start + self.interval_ms));
+            return;
+        }
+
+        while self.windows.back().unwrap().window_start < target_start {
+            let next_start = self.windows.back().unwrap().window_end;
+            self.windows
+                .push_back(Window::new(next_start, next_start + self.interval_ms));
+            if self.windows.len() > self.ring_size {
+                self.windows.pop_front();
+            }
+        }
     }
 
-    /// Check if this aggregator has any data
+    /// Check if the oldest buffered window is ready to be emitted (past
+    /// `window_end + delay_ms`).
+    pub fn is_ready(&mut self, delay_ms: u64) -> bool {
+        self.grow_to(current_window_start(current_timestamp_ms(), self.interval_ms));
+
+        self.windows
+            .front()
+            .is_some_and(|w| current_timestamp_ms() >= w.window_end + delay_ms)
+    }
+
+    /// Check if this aggregator has any data in its oldest buffered window.
     pub fn has_data(&self) -> bool {
-        self.open.is_some()
+        self.windows.front().is_some_and(Window::has_data)
     }
 
-    /// Emit the current bar and reset for the next window
+    /// Finalize and evict the oldest buffered window, replacing it so the
+    /// ring never empties out.
     pub fn emit_and_reset(&mut self) -> Option<MsBar> {
-        if !self.has_data() {
-            // No trades in this window, advance to next window
-            self.advance_window();
+        self.grow_to(current_window_start(current_timestamp_ms(), self.interval_ms));
+
+        let window = self.windows.pop_front()?;
+        if self.windows.is_empty() {
+            self.windows.push_back(Window::new(
+                window.window_end,
+                window.window_end + self.interval_ms,
+            ));
+        }
+
+        if !window.has_data() {
             return None;
         }
 
-        let bar = MsBar {
+        Some(MsBar {
             event_type: "MB".to_string(),
             symbol: self.symbol.clone(),
             interval_ms: self.interval_ms,
-            open: self.open.unwrap(),
-            high: self.high.unwrap(),
-            low: self.low.unwrap(),
-            close: self.close.unwrap(),
-            volume: self.volume,
-            start_timestamp: self.window_start,
-            end_timestamp: self.window_end,
-            num_trades: self.num_trades,
-        };
-
-        // Reset for next window
-        self.advance_window();
-
-        Some(bar)
+            open: window.open.unwrap(),
+            high: window.high.unwrap(),
+            low: window.low.unwrap(),
+            close: window.close.unwrap(),
+            volume: window.volume,
+            start_timestamp: window.window_start,
+            end_timestamp: window.window_end,
+            num_trades: window.num_trades,
+        })
     }
 
-    /// Advance to the next time window
-    fn advance_window(&mut self) {
-        self.window_start = self.window_end;
-        self.window_end = self.window_start + self.interval_ms;
-        self.open = None;
-        self.high = None;
-        self.low = None;
-        self.close = None;
-        self.volume = 0;
-        self.num_trades = 0;
-    }
-
-    /// Force emit current bar (even if not complete) and advance
+    /// Force emit the oldest buffered window (even if not complete) and
+    /// advance.
     pub fn force_emit(&mut self) -> Option<MsBar> {
         self.emit_and_reset()
     }
 
+    /// Trades dropped because they were older than every buffered window or
+    /// too far ahead of the newest one.
+    pub fn dropped_late_trades(&self) -> u64 {
+        self.dropped_late_trades
+    }
+
     pub fn symbol(&self) -> &str {
         &self.symbol
     }
@@ -142,10 +243,22 @@ impl BarAggregator {
     }
 
     pub fn window_end(&self) -> u64 {
-        self.window_end
+        self.windows
+            .back()
+            .map(|w| w.window_end)
+            .unwrap_or(self.interval_ms)
+    }
+
+    #[cfg(test)]
+    fn current_window_start(&self) -> u64 {
+        self.windows.front().map(|w| w.window_start).unwrap_or(0)
     }
 }
 
+fn current_window_start(now_ms: u64, interval_ms: u64) -> u64 {
+    (now_ms / interval_ms) * interval_ms
+}
+
 /// Get current timestamp in milliseconds
 fn current_timestamp_ms() -> u64 {
     SystemTime::now()
@@ -160,27 +273,27 @@ mod tests {
 
     #[test]
     fn test_bar_aggregator_basic() {
-        let mut agg = BarAggregator::new("AAPL".to_string(), 1000);
+        let mut agg = BarAggregator::new("AAPL".to_string(), 1000, 3);
+        let base_time = agg.current_window_start();
 
-        // Create a trade in the current window
         let trade = PolygonTrade {
             symbol: "AAPL".to_string(),
             price: 150.0,
             size: 100,
-            timestamp: agg.window_start + 100,
+            timestamp: base_time + 100,
             extra: serde_json::Value::Null,
         };
 
         agg.add_trade(&trade);
         assert!(agg.has_data());
-        assert_eq!(agg.num_trades, 1);
-        assert_eq!(agg.volume, 100);
+        assert_eq!(agg.windows.front().unwrap().num_trades, 1);
+        assert_eq!(agg.windows.front().unwrap().volume, 100);
     }
 
     #[test]
     fn test_bar_aggregator_ohlc() {
-        let mut agg = BarAggregator::new("AAPL".to_string(), 1000);
-        let base_time = agg.window_start;
+        let mut agg = BarAggregator::new("AAPL".to_string(), 1000, 3);
+        let base_time = agg.current_window_start();
 
         let trades = vec![
             (base_time + 100, 150.0, 100),
@@ -200,18 +313,19 @@ mod tests {
             agg.add_trade(&trade);
         }
 
-        assert_eq!(agg.open, Some(150.0));
-        assert_eq!(agg.high, Some(152.0));
-        assert_eq!(agg.low, Some(149.0));
-        assert_eq!(agg.close, Some(151.0));
-        assert_eq!(agg.volume, 250);
-        assert_eq!(agg.num_trades, 4);
+        let window = agg.windows.front().unwrap();
+        assert_eq!(window.open, Some(150.0));
+        assert_eq!(window.high, Some(152.0));
+        assert_eq!(window.low, Some(149.0));
+        assert_eq!(window.close, Some(151.0));
+        assert_eq!(window.volume, 250);
+        assert_eq!(window.num_trades, 4);
     }
 
     #[test]
     fn test_bar_aggregator_emit() {
-        let mut agg = BarAggregator::new("AAPL".to_string(), 1000);
-        let base_time = agg.window_start;
+        let mut agg = BarAggregator::new("AAPL".to_string(), 1000, 3);
+        let base_time = agg.current_window_start();
 
         let trade = PolygonTrade {
             symbol: "AAPL".to_string(),
@@ -232,7 +346,149 @@ mod tests {
         assert_eq!(bar.open, 150.0);
         assert_eq!(bar.volume, 100);
 
-        // After emit, aggregator should be reset
+        // After emit, the window that held the trade is gone, replaced by
+        // an empty one.
         assert!(!agg.has_data());
     }
+
+    #[test]
+    fn test_out_of_order_trade_within_same_window() {
+        let mut agg = BarAggregator::new("AAPL".to_string(), 1000, 3);
+        let base_time = agg.current_window_start();
+
+        // Latest timestamp arrives first, earliest arrives last - `open`
+        // must still reflect the earliest timestamp, not first-applied.
+        let later = PolygonTrade {
+            symbol: "AAPL".to_string(),
+            price: 151.0,
+            size: 10,
+            timestamp: base_time + 400,
+            extra: serde_json::Value::Null,
+        };
+        let earlier = PolygonTrade {
+            symbol: "AAPL".to_string(),
+            price: 150.0,
+            size: 10,
+            timestamp: base_time + 100,
+            extra: serde_json::Value::Null,
+        };
+
+        agg.add_trade(&later);
+        agg.add_trade(&earlier);
+
+        let window = agg.windows.front().unwrap();
+        assert_eq!(window.open, Some(150.0));
+        assert_eq!(window.close, Some(151.0));
+    }
+
+    #[test]
+    fn test_late_trade_into_prior_window_still_buffered() {
+        let mut agg = BarAggregator::new("AAPL".to_string(), 1000, 3);
+        let base_time = agg.current_window_start();
+
+        let in_window_one = PolygonTrade {
+            symbol: "AAPL".to_string(),
+            price: 150.0,
+            size: 10,
+            timestamp: base_time + 100,
+            extra: serde_json::Value::Null,
+        };
+        agg.add_trade(&in_window_one);
+
+        // A trade for the next window arrives, rolling the ring forward...
+        let in_window_two = PolygonTrade {
+            symbol: "AAPL".to_string(),
+            price: 152.0,
+            size: 10,
+            timestamp: base_time + 1100,
+            extra: serde_json::Value::Null,
+        };
+        assert!(!agg.add_trade(&in_window_two));
+
+        // ...but a late trade that still belongs to window one (within the
+        // ring) must not be dropped.
+        let late_for_window_one = PolygonTrade {
+            symbol: "AAPL".to_string(),
+            price: 149.0,
+            size: 20,
+            timestamp: base_time + 500,
+            extra: serde_json::Value::Null,
+        };
+        assert!(!agg.add_trade(&late_for_window_one));
+        assert_eq!(agg.dropped_late_trades(), 0);
+
+        let bar = agg.emit_and_reset().unwrap();
+        assert_eq!(bar.low, 149.0);
+        assert_eq!(bar.volume, 30);
+    }
+
+    #[test]
+    fn test_trade_older_than_ring_is_dropped() {
+        let mut agg = BarAggregator::new("AAPL".to_string(), 1000, 2);
+        let base_time = agg.current_window_start();
+
+        // Roll the ring forward past window one entirely: ring_size 2 means
+        // only the current window plus one prior stay buffered.
+        for offset in [1100u64, 2100, 3100] {
+            let trade = PolygonTrade {
+                symbol: "AAPL".to_string(),
+                price: 150.0,
+                size: 10,
+                timestamp: base_time + offset,
+                extra: serde_json::Value::Null,
+            };
+            agg.add_trade(&trade);
+        }
+
+        let too_late = PolygonTrade {
+            symbol: "AAPL".to_string(),
+            price: 999.0,
+            size: 1,
+            timestamp: base_time + 100,
+            extra: serde_json::Value::Null,
+        };
+        assert!(agg.add_trade(&too_late));
+        assert_eq!(agg.dropped_late_trades(), 1);
+    }
+
+    #[test]
+    fn test_far_future_trade_timestamp_is_dropped_not_grown() {
+        let mut agg = BarAggregator::new("AAPL".to_string(), 1000, 3);
+        let base_time = agg.current_window_start();
+
+        // A corrupted or unit-mismatched (e.g. seconds instead of millis)
+        // timestamp years ahead of the current window must be dropped
+        // instead of making `grow_to` push millions of windows.
+        let far_future = PolygonTrade {
+            symbol: "AAPL".to_string(),
+            price: 150.0,
+            size: 10,
+            timestamp: base_time + 1_000 * 60 * 60 * 24 * 365,
+            extra: serde_json::Value::Null,
+        };
+
+        assert!(agg.add_trade(&far_future));
+        assert_eq!(agg.dropped_late_trades(), 1);
+        assert_eq!(agg.windows.len(), 1);
+    }
+
+    #[test]
+    fn test_is_ready_only_for_oldest_window_past_delay() {
+        let mut agg = BarAggregator::new("AAPL".to_string(), 1, 3);
+        let base_time = agg.current_window_start();
+
+        let trade = PolygonTrade {
+            symbol: "AAPL".to_string(),
+            price: 150.0,
+            size: 10,
+            timestamp: base_time,
+            extra: serde_json::Value::Null,
+        };
+        agg.add_trade(&trade);
+
+        // 1ms window with no extra delay: give the wall clock a moment to
+        // move past window_end before checking.
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        assert!(agg.is_ready(0));
+    }
 }
@@ -13,6 +13,9 @@ pub struct Config {
     /// Port for the ms-aggregator WebSocket server
     pub aggregator_port: u16,
 
+    /// Port for the Prometheus text-exposition metrics endpoint
+    pub metrics_port: u16,
+
     /// Minimum interval in milliseconds
     pub min_interval_ms: u64,
 
@@ -25,11 +28,39 @@ pub struct Config {
     /// Bar emission delay in milliseconds
     pub bar_delay_ms: u64,
 
+    /// Total windows kept buffered per bar aggregator, the current one
+    /// included, so out-of-order and late trades still land correctly
+    pub bar_ring_size: usize,
+
     /// Log level (trace, debug, info, warn, error)
     pub log_level: String,
 
     /// Enable fake data generation for testing
     pub enable_fake_data: bool,
+
+    /// Base delay for exponential upstream reconnect backoff (ms)
+    pub reconnect_base_ms: u64,
+
+    /// Cap for exponential upstream reconnect backoff (ms)
+    pub reconnect_max_ms: u64,
+
+    /// Treat the upstream socket as dead if no message arrives within this window (secs)
+    pub upstream_idle_timeout_secs: u64,
+
+    /// A connection must stay up at least this long before the backoff resets (secs)
+    pub reconnect_stable_after_secs: u64,
+
+    /// How often to send an application-level ping to each client (ms)
+    pub client_ping_interval_ms: u64,
+
+    /// Evict a client if no pong/activity is seen within this window (ms)
+    pub client_timeout_ms: u64,
+
+    /// Evict a client if its bar channel has been full for longer than this window (ms)
+    pub client_backpressure_timeout_ms: u64,
+
+    /// Total-bytes budget for the rolling trade buffer, across all symbols
+    pub trade_buffer_max_bytes: usize,
 }
 
 impl Config {
@@ -47,6 +78,11 @@ impl Config {
             .parse()
             .context("AGGREGATOR_PORT must be a valid port number")?;
 
+        let metrics_port = env::var("METRICS_PORT")
+            .unwrap_or_else(|_| "9091".to_string())
+            .parse()
+            .context("METRICS_PORT must be a valid port number")?;
+
         let min_interval_ms = env::var("MIN_INTERVAL_MS")
             .unwrap_or_else(|_| "1".to_string())
             .parse()
@@ -67,6 +103,11 @@ impl Config {
             .parse()
             .context("BAR_DELAY_MS must be a valid number")?;
 
+        let bar_ring_size = env::var("BAR_RING_SIZE")
+            .unwrap_or_else(|_| "3".to_string())
+            .parse()
+            .context("BAR_RING_SIZE must be a valid number")?;
+
         let log_level = env::var("LOG_LEVEL")
             .unwrap_or_else(|_| "info".to_string());
 
@@ -74,16 +115,66 @@ impl Config {
             .map(|v| v == "true" || v == "1")
             .unwrap_or(false);
 
+        let reconnect_base_ms = env::var("UPSTREAM_RECONNECT_BASE_MS")
+            .unwrap_or_else(|_| "250".to_string())
+            .parse()
+            .context("UPSTREAM_RECONNECT_BASE_MS must be a valid number")?;
+
+        let reconnect_max_ms = env::var("UPSTREAM_MAX_BACKOFF_MS")
+            .unwrap_or_else(|_| "30000".to_string())
+            .parse()
+            .context("UPSTREAM_MAX_BACKOFF_MS must be a valid number")?;
+
+        let upstream_idle_timeout_secs = env::var("UPSTREAM_IDLE_TIMEOUT_SECS")
+            .unwrap_or_else(|_| "30".to_string())
+            .parse()
+            .context("UPSTREAM_IDLE_TIMEOUT_SECS must be a valid number")?;
+
+        let reconnect_stable_after_secs = env::var("RECONNECT_STABLE_AFTER_SECS")
+            .unwrap_or_else(|_| "60".to_string())
+            .parse()
+            .context("RECONNECT_STABLE_AFTER_SECS must be a valid number")?;
+
+        let client_ping_interval_ms = env::var("CLIENT_PING_INTERVAL_MS")
+            .unwrap_or_else(|_| "15000".to_string())
+            .parse()
+            .context("CLIENT_PING_INTERVAL_MS must be a valid number")?;
+
+        let client_timeout_ms = env::var("CLIENT_TIMEOUT_MS")
+            .unwrap_or_else(|_| "45000".to_string())
+            .parse()
+            .context("CLIENT_TIMEOUT_MS must be a valid number")?;
+
+        let client_backpressure_timeout_ms = env::var("CLIENT_BACKPRESSURE_TIMEOUT_MS")
+            .unwrap_or_else(|_| "5000".to_string())
+            .parse()
+            .context("CLIENT_BACKPRESSURE_TIMEOUT_MS must be a valid number")?;
+
+        let trade_buffer_max_bytes = env::var("TRADE_BUFFER_MAX_BYTES")
+            .unwrap_or_else(|_| "268435456".to_string()) // 256 MiB
+            .parse()
+            .context("TRADE_BUFFER_MAX_BYTES must be a valid number")?;
+
         Ok(Config {
             firehose_url,
             polygon_api_key,
             aggregator_port,
+            metrics_port,
             min_interval_ms,
             max_interval_ms,
             timer_interval_ms,
             bar_delay_ms,
+            bar_ring_size,
             log_level,
             enable_fake_data,
+            reconnect_base_ms,
+            reconnect_max_ms,
+            upstream_idle_timeout_secs,
+            reconnect_stable_after_secs,
+            client_ping_interval_ms,
+            client_timeout_ms,
+            client_backpressure_timeout_ms,
+            trade_buffer_max_bytes,
         })
     }
 
@@ -104,6 +195,10 @@ impl Config {
             anyhow::bail!("timer_interval_ms must be greater than 0");
         }
 
+        if self.bar_ring_size < 2 {
+            anyhow::bail!("bar_ring_size must be at least 2 (current window plus one prior)");
+        }
+
         Ok(())
     }
 }
@@ -1,22 +1,56 @@
 mod bar_aggregator;
 mod config;
+mod metrics;
 mod subscription_manager;
 mod trade_buffer;
 mod types;
 mod upstream;
 
+// jemalloc backs `TradeBufferStats`' resident/allocated byte readings via
+// `jemalloc-ctl`, so the trade buffer's byte budget can be sized against
+// real RSS instead of its own per-trade estimate. Not available on MSVC.
+#[cfg(not(target_env = "msvc"))]
+#[global_allocator]
+static GLOBAL: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+
 use anyhow::{Context, Result};
 use config::Config;
 use dashmap::DashMap;
+use metrics::Metrics;
 use std::sync::Arc;
-use subscription_manager::{ClientId, SubscriptionManager};
+use subscription_manager::{ClientId, SubscribeResult, SubscriptionManager};
 use tokio::net::TcpListener;
-use tokio::sync::mpsc;
-use tracing::{error, info};
+use tokio::sync::{mpsc, Notify};
+use tracing::{error, info, warn};
 use tracing_subscriber::{fmt, EnvFilter};
-use types::{MsBar, PolygonTrade};
+use types::{MsBar, OutboundPayload, PolygonTrade, SubscriptionMessage};
+
+type ClientSenders = Arc<DashMap<ClientId, mpsc::Sender<OutboundPayload>>>;
+/// Fired to tell a client's connection task to tear itself down after the
+/// timer task evicts it as a slow consumer, mirroring firehose-proxy's
+/// `evict_notify` - without this, a client that's still TCP-alive (so the
+/// read loop's own staleness check never fires) would never learn its
+/// sender was removed and would hold its `SubscriptionManager` entries forever.
+type ClientNotify = Arc<DashMap<ClientId, Arc<Notify>>>;
+
+/// With the `tracing-console` feature, serve tokio-console instead of plain
+/// logs so per-task poll times of the trade processor, timer, and per-client
+/// writer tasks can be inspected live while chasing delivery jitter.
+#[cfg(feature = "tracing-console")]
+fn init_tracing(_log_level: &str) {
+    console_subscriber::init();
+}
 
-type ClientSenders = Arc<DashMap<ClientId, mpsc::Sender<MsBar>>>;
+#[cfg(not(feature = "tracing-console"))]
+fn init_tracing(log_level: &str) {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(log_level));
+
+    fmt()
+        .with_env_filter(filter)
+        .with_target(false)
+        .with_thread_ids(false)
+        .init();
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -24,15 +58,8 @@ async fn main() -> Result<()> {
     let config = Config::from_env().context("Failed to load configuration")?;
     config.validate().context("Invalid configuration")?;
 
-    // Initialize logging
-    let filter = EnvFilter::try_from_default_env()
-        .unwrap_or_else(|_| EnvFilter::new(&config.log_level));
-
-    fmt()
-        .with_env_filter(filter)
-        .with_target(false)
-        .with_thread_ids(false)
-        .init();
+    // Initialize logging (or tokio-console, if built with `--features tracing-console`)
+    init_tracing(&config.log_level);
 
     info!("Starting Polygon Millisecond Bar Aggregator");
     info!("Firehose URL: {}", config.firehose_url);
@@ -43,17 +70,35 @@ async fn main() -> Result<()> {
     );
     info!("Timer Interval: {}ms", config.timer_interval_ms);
     info!("Bar Delay: {}ms", config.bar_delay_ms);
+    info!("Bar Ring Size: {} windows", config.bar_ring_size);
+    info!("Metrics Port: {}", config.metrics_port);
+    info!("Trade Buffer Max Bytes: {}", config.trade_buffer_max_bytes);
+
+    // Create metrics and start the scrape endpoint
+    let metrics = Metrics::new();
+    let metrics_handle = tokio::spawn(metrics::serve(metrics.clone(), config.metrics_port));
 
     // Create subscription manager
-    let subscription_manager = Arc::new(SubscriptionManager::new(
+    let subscription_manager = Arc::new(SubscriptionManager::with_trade_buffer_limits(
         config.min_interval_ms,
         config.max_interval_ms,
         config.bar_delay_ms,
+        config.bar_ring_size,
+        metrics.clone(),
+        trade_buffer::BUFFER_DURATION_MS,
+        config.trade_buffer_max_bytes,
     ));
 
     // Create client senders map
     let client_senders: ClientSenders = Arc::new(DashMap::new());
 
+    // Lets the timer task wake an evicted client's own connection task
+    let client_notify: ClientNotify = Arc::new(DashMap::new());
+
+    // Tracks how long each client's bar channel has been continuously full,
+    // so a slow consumer can be evicted instead of holding bars forever
+    let client_full_since: Arc<DashMap<ClientId, tokio::time::Instant>> = Arc::new(DashMap::new());
+
     // Create channels with larger buffer to handle bursts
     // Note: Most trades will be filtered out by early-exit in process_trade()
     let (trade_tx, mut trade_rx) = mpsc::channel::<PolygonTrade>(100000);
@@ -93,7 +138,7 @@ async fn main() -> Result<()> {
             }
         })
     } else {
-        let upstream = upstream::UpstreamConnection::new(config.clone(), trade_tx);
+        let upstream = upstream::UpstreamConnection::new(config.clone(), trade_tx, metrics.clone());
         tokio::spawn(async move {
             if let Err(e) = upstream.run().await {
                 error!("Upstream connection failed: {}", e);
@@ -112,7 +157,11 @@ async fn main() -> Result<()> {
     // Start timer task to check and emit bars
     let subscription_manager_clone = subscription_manager.clone();
     let client_senders_clone = client_senders.clone();
+    let client_full_since_clone = client_full_since.clone();
+    let client_notify_clone = client_notify.clone();
     let timer_interval_ms = config.timer_interval_ms;
+    let client_backpressure_timeout = tokio::time::Duration::from_millis(config.client_backpressure_timeout_ms);
+    let metrics_for_timer = metrics.clone();
     let timer_handle = tokio::spawn(async move {
         let mut interval = tokio::time::interval(tokio::time::Duration::from_millis(
             timer_interval_ms,
@@ -130,9 +179,41 @@ async fn main() -> Result<()> {
 
             for (client_id, bar) in bars {
                 if let Some(sender) = client_senders_clone.get(&client_id) {
-                    if sender.send(bar).await.is_err() {
-                        // Client disconnected, remove sender
-                        client_senders_clone.remove(&client_id);
+                    // try_send rather than a blocking send: a single full client
+                    // channel must never stall delivery to every other client.
+                    match sender.try_send(OutboundPayload::Bars(vec![bar])) {
+                        Ok(()) => {
+                            client_full_since_clone.remove(&client_id);
+                        }
+                        Err(mpsc::error::TrySendError::Full(_)) => {
+                            metrics_for_timer.record_bar_dropped();
+
+                            let full_since = *client_full_since_clone
+                                .entry(client_id)
+                                .or_insert_with(tokio::time::Instant::now);
+
+                            if full_since.elapsed() >= client_backpressure_timeout {
+                                warn!(
+                                    "Evicting client {}: bar channel full for {:?}",
+                                    client_id,
+                                    full_since.elapsed()
+                                );
+                                metrics_for_timer.record_slow_client_evicted();
+                                drop(sender);
+                                client_senders_clone.remove(&client_id);
+                                client_full_since_clone.remove(&client_id);
+                                subscription_manager_clone.remove_client(client_id);
+                                if let Some((_, notify)) = client_notify_clone.remove(&client_id) {
+                                    notify.notify_one();
+                                }
+                            }
+                        }
+                        Err(mpsc::error::TrySendError::Closed(_)) => {
+                            // Client disconnected, remove sender
+                            drop(sender);
+                            client_senders_clone.remove(&client_id);
+                            client_full_since_clone.remove(&client_id);
+                        }
                     }
                 }
             }
@@ -143,9 +224,10 @@ async fn main() -> Result<()> {
 
                 let stats = subscription_manager_clone.stats();
                 info!(
-                    "Stats: {} aggregators, {} clients, buffer: {} symbols, {} trades",
+                    "Stats: {} aggregators, {} clients, buffer: {} symbols, {} trades, {}/{} bytes",
                     stats.num_aggregators, stats.num_clients,
-                    stats.buffer_symbols, stats.buffer_trades
+                    stats.buffer_symbols, stats.buffer_trades,
+                    stats.buffer_estimated_bytes, stats.buffer_max_bytes
                 );
             }
         }
@@ -162,13 +244,21 @@ async fn main() -> Result<()> {
     );
 
     // Start accepting client connections
+    let metrics_for_clients = metrics.clone();
+    let config_for_clients = config.clone();
     let client_handle = tokio::spawn(async move {
         loop {
             match listener.accept().await {
                 Ok((stream, addr)) => {
                     info!("New client connection from {}", addr);
+                    if let Err(e) = stream.set_nodelay(true) {
+                        warn!("Failed to set TCP_NODELAY for {}: {}", addr, e);
+                    }
                     let subscription_manager = subscription_manager.clone();
                     let client_senders = client_senders.clone();
+                    let client_notify = client_notify.clone();
+                    let metrics = metrics_for_clients.clone();
+                    let config = config_for_clients.clone();
 
                     tokio::spawn(async move {
                         if let Err(e) = handle_client_connection(
@@ -176,6 +266,9 @@ async fn main() -> Result<()> {
                             addr,
                             subscription_manager,
                             client_senders,
+                            client_notify,
+                            metrics,
+                            config,
                         )
                         .await
                         {
@@ -196,6 +289,7 @@ async fn main() -> Result<()> {
         _ = trade_processor_handle => error!("Trade processor task exited"),
         _ = timer_handle => error!("Timer task exited"),
         _ = client_handle => error!("Client handler task exited"),
+        _ = metrics_handle => error!("Metrics task exited"),
     }
 
     Ok(())
@@ -206,14 +300,32 @@ async fn handle_client_connection(
     addr: std::net::SocketAddr,
     subscription_manager: Arc<SubscriptionManager>,
     client_senders: ClientSenders,
+    client_notify: ClientNotify,
+    metrics: Arc<Metrics>,
+    config: Config,
 ) -> Result<()> {
-    use crate::types::{AuthMessage, SubscriptionMessage};
+    use crate::types::{AuthMessage, ClientEncoding};
     use futures_util::{SinkExt, StreamExt};
+    use std::sync::atomic::{AtomicU8, Ordering};
+    use std::time::Duration;
     use tokio::sync::mpsc;
+    use tokio::time::Instant;
     use tokio_tungstenite::tungstenite::Message;
     use tracing::{debug, error, info, warn};
     use uuid::Uuid;
 
+    const ENCODING_JSON: u8 = 0;
+    const ENCODING_MSGPACK: u8 = 1;
+    const ENCODING_BINCODE: u8 = 2;
+
+    fn encoding_to_u8(encoding: ClientEncoding) -> u8 {
+        match encoding {
+            ClientEncoding::Json => ENCODING_JSON,
+            ClientEncoding::MsgPack => ENCODING_MSGPACK,
+            ClientEncoding::Bincode => ENCODING_BINCODE,
+        }
+    }
+
     let ws_stream = tokio_tungstenite::accept_async(stream)
         .await
         .context("WebSocket handshake failed")?;
@@ -223,27 +335,73 @@ async fn handle_client_connection(
 
     let (mut write, mut read) = ws_stream.split();
 
-    // Create channel for sending bars to this client
-    let (bar_tx, mut bar_rx) = mpsc::channel::<MsBar>(1000);
+    // Create channel for sending bars (and control-action responses) to this client
+    let (bar_tx, mut bar_rx) = mpsc::channel::<OutboundPayload>(1000);
 
-    // Register client sender
+    // Register client sender, and a Notify the timer task can fire to tear
+    // this connection down the moment it evicts the client as a slow
+    // consumer, rather than leaving it to a future staleness ping that a
+    // TCP-alive-but-slow-to-drain client may keep answering forever.
     client_senders.insert(client_id, bar_tx);
+    let evicted = Arc::new(Notify::new());
+    client_notify.insert(client_id, evicted.clone());
+    metrics.client_connected();
 
     let mut authenticated = false;
+    // Negotiated via the auth frame (always JSON); `Json` until then.
+    // Shared with the writer task below since bars only start flowing once
+    // a client has subscribed, which requires auth to have already run.
+    let mut encoding = ClientEncoding::Json;
+    let encoding_code = Arc::new(AtomicU8::new(ENCODING_JSON));
+
+    // Channel the read loop uses to ask the writer task for an application-level ping
+    let (ping_tx, mut ping_rx) = mpsc::channel::<()>(1);
 
-    // Spawn task to send bars to client
+    // Spawn task to send bars (and pings) to client
     let client_id_for_sender = client_id;
+    let encoding_for_sender = encoding_code.clone();
+    let metrics_for_sender = metrics.clone();
     let mut write_handle = tokio::spawn(async move {
-        while let Some(bar) = bar_rx.recv().await {
-            let msg = serde_json::to_string(&vec![bar]).unwrap();
-            if write.send(Message::Text(msg)).await.is_err() {
-                warn!("Failed to send bar to client {}", client_id_for_sender);
-                break;
+        let mut ping_channel_open = true;
+        loop {
+            tokio::select! {
+                payload = bar_rx.recv() => {
+                    let Some(payload) = payload else { break };
+                    let frame = match encoding_for_sender.load(Ordering::Relaxed) {
+                        ENCODING_MSGPACK => rmp_serde::to_vec(&payload).ok().map(Message::Binary),
+                        ENCODING_BINCODE => bincode::serialize(&payload).ok().map(Message::Binary),
+                        _ => Some(Message::Text(serde_json::to_string(&payload).unwrap())),
+                    };
+                    let Some(frame) = frame else {
+                        warn!("Failed to encode bar for client {}", client_id_for_sender);
+                        continue;
+                    };
+                    if write.send(frame).await.is_err() {
+                        warn!("Failed to send bar to client {}", client_id_for_sender);
+                        metrics_for_sender.record_client_send_failure();
+                        break;
+                    }
+                }
+                maybe_ping = ping_rx.recv(), if ping_channel_open => {
+                    match maybe_ping {
+                        Some(()) => {
+                            if write.send(Message::Ping(Vec::new())).await.is_err() {
+                                warn!("Failed to send ping to client {}", client_id_for_sender);
+                                break;
+                            }
+                        }
+                        None => ping_channel_open = false,
+                    }
+                }
             }
         }
     });
 
     // Process client messages
+    let mut last_activity_at = Instant::now();
+    let mut ping_interval = tokio::time::interval(Duration::from_millis(config.client_ping_interval_ms));
+    let client_timeout = Duration::from_millis(config.client_timeout_ms);
+
     loop {
         tokio::select! {
             Some(msg) = read.next() => {
@@ -254,6 +412,7 @@ async fn handle_client_connection(
                         break;
                     }
                 };
+                last_activity_at = Instant::now();
 
                 match msg {
                     Message::Text(text) => {
@@ -267,7 +426,13 @@ async fn handle_client_connection(
                                     // In production, validate the API key
                                     authenticated = true;
 
-                                    info!("Client {} authenticated", client_id);
+                                    // The auth frame itself is always JSON text (it's what
+                                    // bootstraps the negotiation); everything after it
+                                    // switches to the negotiated encoding.
+                                    encoding = ClientEncoding::parse(auth.encoding.as_deref());
+                                    encoding_code.store(encoding_to_u8(encoding), Ordering::Relaxed);
+
+                                    info!("Client {} authenticated, encoding={:?}", client_id, encoding);
                                     continue;
                                 }
                             }
@@ -276,60 +441,43 @@ async fn handle_client_connection(
                             continue;
                         }
 
+                        if encoding != ClientEncoding::Json {
+                            warn!(
+                                "Protocol error: client {} negotiated {:?} but sent a text frame, disconnecting",
+                                client_id, encoding
+                            );
+                            break;
+                        }
+
                         // Try to parse as subscription message
                         if let Ok(sub) = serde_json::from_str::<SubscriptionMessage>(&text) {
-                            match sub.action.as_str() {
-                                "subscribe" => {
-                                    match subscription_manager.subscribe(client_id, &sub.params) {
-                                        Ok(subscribed) => {
-                                            info!(
-                                                "Client {} subscribed to: {}{}",
-                                                client_id,
-                                                subscribed.join(", "),
-                                                sub.since.map(|s| format!(" (since={})", s)).unwrap_or_default()
-                                            );
-
-                                            // If 'since' provided, replay buffered bars
-                                            if let Some(since_ms) = sub.since {
-                                                if let Some(sender) = client_senders.get(&client_id) {
-                                                    for sub_str in &subscribed {
-                                                        if let Some(bar_sub) = types::parse_ms_subscription(sub_str) {
-                                                            let bars = subscription_manager.generate_bars_since(
-                                                                &bar_sub.symbol,
-                                                                bar_sub.interval_ms,
-                                                                since_ms,
-                                                            );
-                                                            if !bars.is_empty() {
-                                                                info!(
-                                                                    "Replaying {} buffered bars for {}.{}Ms",
-                                                                    bars.len(), bar_sub.symbol, bar_sub.interval_ms
-                                                                );
-                                                                // Send each bar to the client
-                                                                for bar in bars {
-                                                                    let _ = sender.send(bar).await;
-                                                                }
-                                                            }
-                                                        }
-                                                    }
-                                                }
-                                            }
-                                        }
-                                        Err(e) => {
-                                            warn!("Client {} subscription error: {}", client_id, e);
-                                        }
-                                    }
-                                }
-                                "unsubscribe" => {
-                                    if let Err(e) = subscription_manager.unsubscribe(client_id, &sub.params) {
-                                        warn!("Client {} unsubscribe error: {}", client_id, e);
-                                    } else {
-                                        info!("Client {} unsubscribed from: {}", client_id, sub.params);
-                                    }
-                                }
-                                _ => {
-                                    warn!("Unknown action from client {}: {}", client_id, sub.action);
-                                }
+                            dispatch_subscription_message(sub, client_id, &subscription_manager, &client_senders).await;
+                        }
+                    }
+                    Message::Binary(data) => {
+                        if !authenticated {
+                            warn!("Client {} not authenticated", client_id);
+                            continue;
+                        }
+
+                        if encoding == ClientEncoding::Json {
+                            warn!(
+                                "Protocol error: client {} is in JSON mode but sent a binary frame, disconnecting",
+                                client_id
+                            );
+                            break;
+                        }
+
+                        let sub = match encoding {
+                            ClientEncoding::MsgPack => rmp_serde::from_slice::<SubscriptionMessage>(&data).ok(),
+                            ClientEncoding::Bincode => bincode::deserialize::<SubscriptionMessage>(&data).ok(),
+                            ClientEncoding::Json => unreachable!("handled above"),
+                        };
+                        match sub {
+                            Some(sub) => {
+                                dispatch_subscription_message(sub, client_id, &subscription_manager, &client_senders).await;
                             }
+                            None => debug!("Client {} sent an undecodable {:?} frame", client_id, encoding),
                         }
                     }
                     Message::Close(_) => {
@@ -339,20 +487,132 @@ async fn handle_client_connection(
                     Message::Ping(_) => {
                         // Ping/pong is handled automatically by tokio-tungstenite
                     }
+                    Message::Pong(_) => {
+                        // last_activity_at already bumped above
+                    }
                     _ => {}
                 }
             }
+            _ = ping_interval.tick() => {
+                if last_activity_at.elapsed() >= client_timeout {
+                    warn!(
+                        "Client {} timed out (no activity for {:?}), evicting",
+                        client_id,
+                        last_activity_at.elapsed()
+                    );
+                    metrics.record_stale_client_evicted();
+                    break;
+                }
+                let _ = ping_tx.try_send(());
+            }
             _ = &mut write_handle => {
                 info!("Client {} write task ended", client_id);
                 break;
             }
+            _ = evicted.notified() => {
+                warn!("Client {} evicted as a slow consumer", client_id);
+                break;
+            }
         }
     }
 
     // Clean up
     client_senders.remove(&client_id);
+    client_notify.remove(&client_id);
     subscription_manager.remove_client(client_id);
+    metrics.client_disconnected();
     info!("Client {} cleaned up", client_id);
 
     Ok(())
 }
+
+/// Apply a parsed subscribe/unsubscribe frame, backfilling already-closed
+/// bars from the trade buffer when `since` is set. Shared between the
+/// JSON-text and binary frame-decoding paths in `handle_client_connection`,
+/// which differ only in how they get from wire bytes to a
+/// `SubscriptionMessage`.
+async fn dispatch_subscription_message(
+    sub: SubscriptionMessage,
+    client_id: ClientId,
+    subscription_manager: &SubscriptionManager,
+    client_senders: &ClientSenders,
+) {
+    match sub.action.as_str() {
+        "subscribe" => {
+            let result = match sub.since {
+                Some(since_ms) => subscription_manager
+                    .subscribe_with_backfill(client_id, &sub.params, since_ms),
+                None => subscription_manager
+                    .subscribe(client_id, &sub.params)
+                    .map(|subscribed| SubscribeResult {
+                        subscribed,
+                        backfill: Vec::new(),
+                    }),
+            };
+
+            match result {
+                Ok(result) => {
+                    info!(
+                        "Client {} subscribed to: {}{}",
+                        client_id,
+                        result.subscribed.join(", "),
+                        sub.since.map(|s| format!(" (since={})", s)).unwrap_or_default()
+                    );
+
+                    if !result.backfill.is_empty() {
+                        info!(
+                            "Backfilling {} bars for client {}",
+                            result.backfill.len(),
+                            client_id
+                        );
+                        // try_send, not a blocking send: same non-blocking idiom as the
+                        // timer task's bar emission above, so a backfill too large for a
+                        // nearly-full channel can't stall every other client's dispatch.
+                        if let Some(sender) = client_senders.get(&client_id) {
+                            if sender.try_send(OutboundPayload::Bars(result.backfill)).is_err() {
+                                warn!("Client {} backfill dropped: channel full", client_id);
+                            }
+                        }
+                    }
+                }
+                Err(e) => {
+                    warn!("Client {} subscription error: {}", client_id, e);
+                }
+            }
+        }
+        "unsubscribe" => {
+            if let Err(e) = subscription_manager.unsubscribe(client_id, &sub.params) {
+                warn!("Client {} unsubscribe error: {}", client_id, e);
+            } else {
+                info!("Client {} unsubscribed from: {}", client_id, sub.params);
+            }
+        }
+        "getMarkets" => {
+            let markets = subscription_manager.markets();
+            if let Some(sender) = client_senders.get(&client_id) {
+                if sender.try_send(OutboundPayload::Markets(markets)).is_err() {
+                    warn!("Client {} getMarkets reply dropped: channel full", client_id);
+                }
+            }
+        }
+        "getSnapshot" => {
+            let bars = subscription_manager.snapshot(&sub.params);
+            info!(
+                "Client {} snapshot for {}: {} bars",
+                client_id,
+                sub.params,
+                bars.len()
+            );
+            if !bars.is_empty() {
+                if let Some(sender) = client_senders.get(&client_id) {
+                    if sender.try_send(OutboundPayload::Bars(bars)).is_err() {
+                        warn!("Client {} getSnapshot reply dropped: channel full", client_id);
+                    }
+                }
+            }
+        }
+        _ => {
+            warn!("Unknown action from client {}: {}", client_id, sub.action);
+        }
+    }
+}
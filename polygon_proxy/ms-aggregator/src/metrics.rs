@@ -0,0 +1,229 @@
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpListener;
+use tracing::{error, info};
+
+/// Runtime counters for the ms-aggregator, exposed in Prometheus text
+/// exposition format over `/metrics`.
+#[derive(Default)]
+pub struct Metrics {
+    trades_received_total: AtomicU64,
+    trades_filtered_total: AtomicU64,
+    trades_processed_total: AtomicU64,
+    trades_dropped_late_total: AtomicU64,
+    bars_emitted_total: AtomicU64,
+    bars_dropped_total: AtomicU64,
+    client_send_failures_total: AtomicU64,
+    connected_clients: AtomicI64,
+    active_aggregators: AtomicI64,
+    upstream_reconnects_total: AtomicU64,
+    stale_clients_evicted_total: AtomicU64,
+    slow_clients_evicted_total: AtomicU64,
+}
+
+impl Metrics {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    pub fn record_trade_received(&self) {
+        self.trades_received_total.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_trade_filtered(&self) {
+        self.trades_filtered_total.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_trade_processed(&self) {
+        self.trades_processed_total.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_trade_dropped_late(&self) {
+        self.trades_dropped_late_total.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_bar_emitted(&self) {
+        self.bars_emitted_total.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_bar_dropped(&self) {
+        self.bars_dropped_total.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_client_send_failure(&self) {
+        self.client_send_failures_total
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn client_connected(&self) {
+        self.connected_clients.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn client_disconnected(&self) {
+        self.connected_clients.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn aggregator_created(&self) {
+        self.active_aggregators.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn aggregator_removed(&self) {
+        self.active_aggregators.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn record_reconnect(&self) {
+        self.upstream_reconnects_total
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_stale_client_evicted(&self) {
+        self.stale_clients_evicted_total
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_slow_client_evicted(&self) {
+        self.slow_clients_evicted_total
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Render all counters/gauges in Prometheus text exposition format.
+    fn render(&self) -> String {
+        let mut out = String::new();
+
+        out.push_str("# HELP trades_received_total Trades received from the firehose upstream\n");
+        out.push_str("# TYPE trades_received_total counter\n");
+        out.push_str(&format!(
+            "trades_received_total {}\n",
+            self.trades_received_total.load(Ordering::Relaxed)
+        ));
+
+        out.push_str("# HELP trades_filtered_total Trades with no matching aggregator, skipped\n");
+        out.push_str("# TYPE trades_filtered_total counter\n");
+        out.push_str(&format!(
+            "trades_filtered_total {}\n",
+            self.trades_filtered_total.load(Ordering::Relaxed)
+        ));
+
+        out.push_str("# HELP trades_processed_total Trades applied to at least one aggregator\n");
+        out.push_str("# TYPE trades_processed_total counter\n");
+        out.push_str(&format!(
+            "trades_processed_total {}\n",
+            self.trades_processed_total.load(Ordering::Relaxed)
+        ));
+
+        out.push_str(
+            "# HELP trades_dropped_late_total Trades an aggregator dropped for fitting none of its buffered windows (too old or too far ahead), counted per aggregator\n",
+        );
+        out.push_str("# TYPE trades_dropped_late_total counter\n");
+        out.push_str(&format!(
+            "trades_dropped_late_total {}\n",
+            self.trades_dropped_late_total.load(Ordering::Relaxed)
+        ));
+
+        out.push_str("# HELP bars_emitted_total Millisecond bars emitted by aggregators\n");
+        out.push_str("# TYPE bars_emitted_total counter\n");
+        out.push_str(&format!(
+            "bars_emitted_total {}\n",
+            self.bars_emitted_total.load(Ordering::Relaxed)
+        ));
+
+        out.push_str(
+            "# HELP bars_dropped_total Bars dropped because a client's channel was full\n",
+        );
+        out.push_str("# TYPE bars_dropped_total counter\n");
+        out.push_str(&format!(
+            "bars_dropped_total {}\n",
+            self.bars_dropped_total.load(Ordering::Relaxed)
+        ));
+
+        out.push_str("# HELP client_send_failures_total WebSocket send failures to clients\n");
+        out.push_str("# TYPE client_send_failures_total counter\n");
+        out.push_str(&format!(
+            "client_send_failures_total {}\n",
+            self.client_send_failures_total.load(Ordering::Relaxed)
+        ));
+
+        out.push_str("# HELP connected_clients Currently connected WebSocket clients\n");
+        out.push_str("# TYPE connected_clients gauge\n");
+        out.push_str(&format!(
+            "connected_clients {}\n",
+            self.connected_clients.load(Ordering::Relaxed)
+        ));
+
+        out.push_str(
+            "# HELP active_aggregators Currently active symbol+interval bar aggregators\n",
+        );
+        out.push_str("# TYPE active_aggregators gauge\n");
+        out.push_str(&format!(
+            "active_aggregators {}\n",
+            self.active_aggregators.load(Ordering::Relaxed)
+        ));
+
+        out.push_str(
+            "# HELP upstream_reconnects_total Reconnect attempts to the firehose upstream\n",
+        );
+        out.push_str("# TYPE upstream_reconnects_total counter\n");
+        out.push_str(&format!(
+            "upstream_reconnects_total {}\n",
+            self.upstream_reconnects_total.load(Ordering::Relaxed)
+        ));
+
+        out.push_str("# HELP stale_clients_evicted_total Clients evicted for missing ping/pong liveness\n");
+        out.push_str("# TYPE stale_clients_evicted_total counter\n");
+        out.push_str(&format!(
+            "stale_clients_evicted_total {}\n",
+            self.stale_clients_evicted_total.load(Ordering::Relaxed)
+        ));
+
+        out.push_str(
+            "# HELP slow_clients_evicted_total Clients evicted for sustained bar channel backpressure\n",
+        );
+        out.push_str("# TYPE slow_clients_evicted_total counter\n");
+        out.push_str(&format!(
+            "slow_clients_evicted_total {}\n",
+            self.slow_clients_evicted_total.load(Ordering::Relaxed)
+        ));
+
+        out
+    }
+}
+
+/// Serve `/metrics` on `port` until the process exits.
+pub async fn serve(metrics: Arc<Metrics>, port: u16) {
+    let addr = format!("0.0.0.0:{}", port);
+    let listener = match TcpListener::bind(&addr).await {
+        Ok(l) => l,
+        Err(e) => {
+            error!("Failed to bind metrics listener on {}: {}", addr, e);
+            return;
+        }
+    };
+    info!("Metrics endpoint listening on {}/metrics", addr);
+
+    loop {
+        let (mut stream, _) = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                error!("Metrics listener accept error: {}", e);
+                continue;
+            }
+        };
+
+        let metrics = metrics.clone();
+        tokio::spawn(async move {
+            // We only serve GET /metrics; drain and ignore the request itself.
+            let mut buf = [0u8; 1024];
+            let _ = tokio::io::AsyncReadExt::read(&mut stream, &mut buf).await;
+
+            let body = metrics.render();
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            let _ = stream.write_all(response.as_bytes()).await;
+            let _ = stream.shutdown().await;
+        });
+    }
+}
@@ -1,8 +1,9 @@
 use crate::bar_aggregator::BarAggregator;
+use crate::metrics::Metrics;
 use crate::trade_buffer::TradeBuffer;
-use crate::types::{BarKey, MsBar, PolygonTrade, parse_ms_subscription};
+use crate::types::{BarKey, MarketInfo, MsBar, PolygonTrade, parse_ms_subscription};
 use dashmap::DashMap;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use tracing::{debug, info};
 use uuid::Uuid;
@@ -26,25 +27,68 @@ pub struct SubscriptionManager {
     /// Rolling trade buffer for all symbols (60 seconds)
     trade_buffer: Arc<TradeBuffer>,
 
+    /// Latest emitted bar per (symbol, interval), served by `getSnapshot`
+    checkpoints: Arc<DashMap<BarKey, MsBar>>,
+
     /// Bar emission delay in milliseconds
     bar_delay_ms: u64,
 
+    /// Total windows kept buffered per aggregator, the current one
+    /// included, for out-of-order/late trades.
+    bar_ring_size: usize,
+
     /// Min/max interval validation
     min_interval_ms: u64,
     max_interval_ms: u64,
+
+    metrics: Arc<Metrics>,
 }
 
 impl SubscriptionManager {
-    pub fn new(min_interval_ms: u64, max_interval_ms: u64, bar_delay_ms: u64) -> Self {
+    pub fn new(
+        min_interval_ms: u64,
+        max_interval_ms: u64,
+        bar_delay_ms: u64,
+        bar_ring_size: usize,
+        metrics: Arc<Metrics>,
+    ) -> Self {
+        Self::with_trade_buffer_limits(
+            min_interval_ms,
+            max_interval_ms,
+            bar_delay_ms,
+            bar_ring_size,
+            metrics,
+            crate::trade_buffer::BUFFER_DURATION_MS,
+            crate::trade_buffer::DEFAULT_MAX_BYTES,
+        )
+    }
+
+    /// Same as `new`, but with an explicit trade-buffer age/byte budget
+    /// instead of the defaults.
+    pub fn with_trade_buffer_limits(
+        min_interval_ms: u64,
+        max_interval_ms: u64,
+        bar_delay_ms: u64,
+        bar_ring_size: usize,
+        metrics: Arc<Metrics>,
+        trade_buffer_max_age_ms: u64,
+        trade_buffer_max_bytes: usize,
+    ) -> Self {
         Self {
             aggregators: Arc::new(DashMap::new()),
             client_subscriptions: Arc::new(DashMap::new()),
             key_to_clients: Arc::new(DashMap::new()),
             wildcard_subscriptions: Arc::new(DashMap::new()),
-            trade_buffer: Arc::new(TradeBuffer::new()),
+            trade_buffer: Arc::new(TradeBuffer::with_limits(
+                trade_buffer_max_age_ms,
+                trade_buffer_max_bytes,
+            )),
+            checkpoints: Arc::new(DashMap::new()),
             bar_delay_ms,
+            bar_ring_size,
             min_interval_ms,
             max_interval_ms,
+            metrics,
         }
     }
 
@@ -53,9 +97,59 @@ impl SubscriptionManager {
         &self.trade_buffer
     }
 
-    /// Generate bars from buffered trades for a symbol since a given timestamp
-    pub fn generate_bars_since(&self, symbol: &str, interval_ms: u64, since_ms: u64) -> Vec<MsBar> {
-        self.trade_buffer.generate_bars_since(symbol, interval_ms, since_ms)
+    /// List symbols currently being aggregated, with their active interval sizes.
+    /// Backs the `getMarkets` action.
+    pub fn markets(&self) -> Vec<MarketInfo> {
+        let mut by_symbol: HashMap<String, Vec<u64>> = HashMap::new();
+        for entry in self.aggregators.iter() {
+            by_symbol
+                .entry(entry.key().symbol.clone())
+                .or_default()
+                .push(entry.key().interval_ms);
+        }
+
+        by_symbol
+            .into_iter()
+            .map(|(symbol, mut intervals_ms)| {
+                intervals_ms.sort_unstable();
+                MarketInfo {
+                    symbol,
+                    intervals_ms,
+                }
+            })
+            .collect()
+    }
+
+    /// Most recent completed bar per (symbol, interval) matching `params`
+    /// (same format as `subscribe`, wildcards included). Backs the
+    /// `getSnapshot` action, letting a reconnecting client prime its view
+    /// in O(1) instead of replaying the trade buffer.
+    pub fn snapshot(&self, params: &str) -> Vec<MsBar> {
+        let mut bars = Vec::new();
+
+        for sub in params.split(',').map(|s| s.trim()) {
+            let Some(bar_sub) = parse_ms_subscription(sub) else {
+                continue;
+            };
+
+            if bar_sub.symbol == "*" {
+                for entry in self.checkpoints.iter() {
+                    if entry.key().interval_ms == bar_sub.interval_ms {
+                        bars.push(entry.value().clone());
+                    }
+                }
+            } else {
+                let key = BarKey {
+                    symbol: bar_sub.symbol.clone(),
+                    interval_ms: bar_sub.interval_ms,
+                };
+                if let Some(bar) = self.checkpoints.get(&key) {
+                    bars.push(bar.clone());
+                }
+            }
+        }
+
+        bars
     }
 
     /// Subscribe a client to one or more bar intervals
@@ -103,7 +197,8 @@ impl SubscriptionManager {
                             .entry(key.clone())
                             .or_insert_with(|| {
                                 info!("Created aggregator for {}.{}Ms", key.symbol, key.interval_ms);
-                                BarAggregator::new(key.symbol.clone(), key.interval_ms)
+                                self.metrics.aggregator_created();
+                                BarAggregator::new(key.symbol.clone(), key.interval_ms, self.bar_ring_size)
                             });
 
                         // Add to client subscriptions
@@ -135,6 +230,45 @@ impl SubscriptionManager {
         }
     }
 
+    /// Subscribe a client, same as `subscribe`, and additionally backfill
+    /// already-closed bars since `since_ms` so the client isn't left with a
+    /// gap until the next bar boundary closes. Wildcard subscriptions
+    /// backfill every symbol currently known to the trade buffer at that
+    /// interval.
+    pub fn subscribe_with_backfill(
+        &self,
+        client_id: ClientId,
+        params: &str,
+        since_ms: u64,
+    ) -> Result<SubscribeResult, String> {
+        let subscribed = self.subscribe(client_id, params)?;
+        let mut backfill = Vec::new();
+
+        for sub in &subscribed {
+            let Some(bar_sub) = parse_ms_subscription(sub) else {
+                continue;
+            };
+
+            if bar_sub.symbol == "*" {
+                for symbol in self.trade_buffer.symbols() {
+                    backfill.extend(self.trade_buffer.generate_bars_since(
+                        &symbol,
+                        bar_sub.interval_ms,
+                        since_ms,
+                    ));
+                }
+            } else {
+                backfill.extend(self.trade_buffer.generate_bars_since(
+                    &bar_sub.symbol,
+                    bar_sub.interval_ms,
+                    since_ms,
+                ));
+            }
+        }
+
+        Ok(SubscribeResult { subscribed, backfill })
+    }
+
     /// Unsubscribe a client from bar intervals
     pub fn unsubscribe(&self, client_id: ClientId, params: &str) -> Result<(), String> {
         let subscriptions: Vec<&str> = params.split(',').map(|s| s.trim()).collect();
@@ -165,7 +299,9 @@ impl SubscriptionManager {
                         if clients.is_empty() {
                             drop(clients);
                             self.key_to_clients.remove(&key);
-                            self.aggregators.remove(&key);
+                            if self.aggregators.remove(&key).is_some() {
+                                self.metrics.aggregator_removed();
+                            }
                             debug!("Removed aggregator for {:?}", key);
                         }
                     }
@@ -189,7 +325,9 @@ impl SubscriptionManager {
                     if clients.is_empty() {
                         drop(clients);
                         self.key_to_clients.remove(&key);
-                        self.aggregators.remove(&key);
+                        if self.aggregators.remove(&key).is_some() {
+                            self.metrics.aggregator_removed();
+                        }
                         debug!("Removed aggregator for {:?}", key);
                     }
                 }
@@ -204,11 +342,14 @@ impl SubscriptionManager {
 
     /// Process a trade and update relevant aggregators
     pub fn process_trade(&self, trade: &PolygonTrade) {
+        self.metrics.record_trade_received();
+
         // Always store in buffer (for historical replay)
         self.trade_buffer.store(trade);
 
         // Early exit: if no aggregators exist, skip further processing
         if self.aggregators.is_empty() {
+            self.metrics.record_trade_filtered();
             return;
         }
 
@@ -228,9 +369,12 @@ impl SubscriptionManager {
 
         // Early exit: if no aggregators for this symbol, skip
         if matching_keys.is_empty() {
+            self.metrics.record_trade_filtered();
             return;
         }
 
+        self.metrics.record_trade_processed();
+
         // Update each matching aggregator
         for key in matching_keys {
             if let Some(mut agg) = self.aggregators.get_mut(&key) {
@@ -238,7 +382,9 @@ impl SubscriptionManager {
                     "Processing {} trade: ${} @ {} size={}",
                     trade.symbol, trade.price, trade.timestamp, trade.size
                 );
-                agg.add_trade(trade);
+                if agg.add_trade(trade) {
+                    self.metrics.record_trade_dropped_late();
+                }
             }
         }
     }
@@ -255,6 +401,9 @@ impl SubscriptionManager {
 
             if agg.is_ready(self.bar_delay_ms) {
                 if let Some(bar) = agg.emit_and_reset() {
+                    self.metrics.record_bar_emitted();
+                    self.checkpoints.insert(key.clone(), bar.clone());
+
                     // Find all clients subscribed to this bar
                     let mut recipients = HashSet::new();
 
@@ -301,6 +450,8 @@ impl SubscriptionManager {
             num_wildcard_clients: self.wildcard_subscriptions.len(),
             buffer_symbols: buffer_stats.num_symbols,
             buffer_trades: buffer_stats.total_trades,
+            buffer_estimated_bytes: buffer_stats.estimated_bytes,
+            buffer_max_bytes: buffer_stats.max_bytes,
         }
     }
 
@@ -310,6 +461,16 @@ impl SubscriptionManager {
     }
 }
 
+/// Result of [`SubscriptionManager::subscribe_with_backfill`]: the confirmed
+/// subscription strings plus an ordered, interval-aligned backfill of
+/// already-closed bars for a freshly (re)connected client to stitch onto
+/// the live stream.
+#[derive(Debug)]
+pub struct SubscribeResult {
+    pub subscribed: Vec<String>,
+    pub backfill: Vec<MsBar>,
+}
+
 #[derive(Debug)]
 pub struct SubscriptionStats {
     pub num_aggregators: usize,
@@ -317,6 +478,8 @@ pub struct SubscriptionStats {
     pub num_wildcard_clients: usize,
     pub buffer_symbols: usize,
     pub buffer_trades: usize,
+    pub buffer_estimated_bytes: usize,
+    pub buffer_max_bytes: usize,
 }
 
 #[cfg(test)]
@@ -325,7 +488,7 @@ mod tests {
 
     #[test]
     fn test_subscribe_specific() {
-        let mgr = SubscriptionManager::new(1, 60000, 20);
+        let mgr = SubscriptionManager::new(1, 60000, 20, 3, Metrics::new());
         let client_id = Uuid::new_v4();
 
         let result = mgr.subscribe(client_id, "100Ms.AAPL,250Ms.AAPL");
@@ -338,7 +501,7 @@ mod tests {
 
     #[test]
     fn test_subscribe_wildcard() {
-        let mgr = SubscriptionManager::new(1, 60000, 20);
+        let mgr = SubscriptionManager::new(1, 60000, 20, 3, Metrics::new());
         let client_id = Uuid::new_v4();
 
         let result = mgr.subscribe(client_id, "100Ms.*");
@@ -350,7 +513,7 @@ mod tests {
 
     #[test]
     fn test_subscribe_invalid_interval() {
-        let mgr = SubscriptionManager::new(1, 60000, 20);
+        let mgr = SubscriptionManager::new(1, 60000, 20, 3, Metrics::new());
         let client_id = Uuid::new_v4();
 
         let result = mgr.subscribe(client_id, "60001Ms.AAPL");
@@ -360,9 +523,39 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_subscribe_with_backfill() {
+        let mgr = SubscriptionManager::new(1, 60000, 20, 3, Metrics::new());
+        let client_id = Uuid::new_v4();
+
+        mgr.trade_buffer().store(&PolygonTrade {
+            symbol: "AAPL".to_string(),
+            price: 150.0,
+            size: 100,
+            timestamp: 1000,
+            extra: serde_json::Value::Null,
+        });
+        mgr.trade_buffer().store(&PolygonTrade {
+            symbol: "AAPL".to_string(),
+            price: 151.0,
+            size: 200,
+            timestamp: 1300,
+            extra: serde_json::Value::Null,
+        });
+
+        let result = mgr
+            .subscribe_with_backfill(client_id, "250Ms.AAPL", 1000)
+            .unwrap();
+
+        assert_eq!(result.subscribed, vec!["250Ms.AAPL".to_string()]);
+        assert_eq!(result.backfill.len(), 2);
+        assert_eq!(result.backfill[0].start_timestamp, 1000);
+        assert_eq!(result.backfill[1].start_timestamp, 1250);
+    }
+
     #[test]
     fn test_remove_client() {
-        let mgr = SubscriptionManager::new(1, 60000, 20);
+        let mgr = SubscriptionManager::new(1, 60000, 20, 3, Metrics::new());
         let client_id = Uuid::new_v4();
 
         mgr.subscribe(client_id, "100Ms.AAPL").unwrap();
@@ -1,11 +1,23 @@
 use crate::types::{MsBar, PolygonTrade};
+use base64::engine::general_purpose;
+use base64::Engine as _;
 use dashmap::DashMap;
 use std::collections::VecDeque;
+use std::mem;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::{SystemTime, UNIX_EPOCH};
 use tracing::debug;
 
 /// Buffer duration in milliseconds (60 seconds)
-const BUFFER_DURATION_MS: u64 = 60_000;
+pub(crate) const BUFFER_DURATION_MS: u64 = 60_000;
+
+/// Default total-bytes budget across all symbols (256 MiB of
+/// `BufferedTrade`s), used when a caller doesn't size one explicitly.
+pub(crate) const DEFAULT_MAX_BYTES: usize = 256 * 1024 * 1024;
+
+/// Version tag prefixed to every `snapshot()` payload, so a future layout
+/// change fails loudly on `restore()` instead of silently misparsing.
+const SNAPSHOT_FORMAT_VERSION: u8 = 1;
 
 /// A simple trade record for buffering (smaller than full PolygonTrade)
 #[derive(Debug, Clone)]
@@ -31,17 +43,33 @@ pub struct TradeBuffer {
     trades: DashMap<String, VecDeque<BufferedTrade>>,
     /// Maximum age of trades to keep (ms)
     max_age_ms: u64,
+    /// Total-bytes budget across all symbols, enforced on every `store`
+    max_bytes: usize,
+    /// Running estimate of buffered bytes: `len * size_of::<BufferedTrade>()`
+    /// summed across every symbol queue. Kept as a counter rather than
+    /// recomputed per store so enforcing the budget doesn't require walking
+    /// every queue on the hot path.
+    bytes_in_use: AtomicUsize,
 }
 
 impl TradeBuffer {
     pub fn new() -> Self {
-        Self::with_duration(BUFFER_DURATION_MS)
+        Self::with_limits(BUFFER_DURATION_MS, DEFAULT_MAX_BYTES)
     }
 
     pub fn with_duration(max_age_ms: u64) -> Self {
+        Self::with_limits(max_age_ms, DEFAULT_MAX_BYTES)
+    }
+
+    /// Construct a buffer bounded by both trade age and a total-bytes budget.
+    /// `max_bytes` should be sized against real allocator RSS (see
+    /// [`TradeBufferStats::allocator_resident_bytes`]), not guessed.
+    pub fn with_limits(max_age_ms: u64, max_bytes: usize) -> Self {
         Self {
             trades: DashMap::new(),
             max_age_ms,
+            max_bytes,
+            bytes_in_use: AtomicUsize::new(0),
         }
     }
 
@@ -50,11 +78,17 @@ impl TradeBuffer {
         let buffered = BufferedTrade::from(trade);
         let symbol = trade.symbol.clone();
 
-        let mut queue = self.trades.entry(symbol).or_insert_with(VecDeque::new);
-        queue.push_back(buffered);
+        {
+            let mut queue = self.trades.entry(symbol).or_insert_with(VecDeque::new);
+            queue.push_back(buffered);
+            self.bytes_in_use
+                .fetch_add(mem::size_of::<BufferedTrade>(), Ordering::Relaxed);
 
-        // Prune old trades from the front
-        self.prune_queue(&mut queue, trade.timestamp);
+            // Prune old trades from the front
+            self.prune_queue(&mut queue, trade.timestamp);
+        }
+
+        self.enforce_byte_budget();
     }
 
     /// Prune trades older than max_age_ms from the front of the queue
@@ -63,6 +97,37 @@ impl TradeBuffer {
         while let Some(front) = queue.front() {
             if front.timestamp < cutoff {
                 queue.pop_front();
+                self.bytes_in_use
+                    .fetch_sub(mem::size_of::<BufferedTrade>(), Ordering::Relaxed);
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// Evict the globally oldest trade across all symbols, repeatedly, until
+    /// back under `max_bytes`. Per-trade rather than per-symbol-queue so a
+    /// single bursty symbol doesn't get to monopolize the whole budget.
+    fn enforce_byte_budget(&self) {
+        while self.bytes_in_use.load(Ordering::Relaxed) > self.max_bytes {
+            let oldest = self
+                .trades
+                .iter()
+                .filter_map(|entry| entry.value().front().map(|t| (entry.key().clone(), t.timestamp)))
+                .min_by_key(|(_, ts)| *ts);
+
+            let Some((symbol, _)) = oldest else {
+                break;
+            };
+
+            if let Some(mut queue) = self.trades.get_mut(&symbol) {
+                if queue.pop_front().is_some() {
+                    self.bytes_in_use
+                        .fetch_sub(mem::size_of::<BufferedTrade>(), Ordering::Relaxed);
+                    debug!("Evicted oldest trade for {} to stay under byte budget", symbol);
+                } else {
+                    break;
+                }
             } else {
                 break;
             }
@@ -117,9 +182,28 @@ impl TradeBuffer {
                 .collect();
 
             if !window_trades.is_empty() {
-                // Compute OHLCV
-                let open = window_trades.first().unwrap().price;
-                let close = window_trades.last().unwrap().price;
+                // Compute OHLCV. `store` only ever `push_back`s, so a late
+                // trade can land out of chronological order in the deque;
+                // open/close must be derived from timestamp order, not
+                // buffer insertion order. Walked with the same <=/>= tie-break
+                // as `Window::add_trade` (on an exact-timestamp tie, the
+                // later-iterated trade wins both open and close), so a
+                // backfilled bar agrees with the live-computed one for the
+                // same window.
+                let mut open = window_trades[0].price;
+                let mut open_ts = window_trades[0].timestamp;
+                let mut close = window_trades[0].price;
+                let mut close_ts = window_trades[0].timestamp;
+                for t in &window_trades[1..] {
+                    if t.timestamp <= open_ts {
+                        open = t.price;
+                        open_ts = t.timestamp;
+                    }
+                    if t.timestamp >= close_ts {
+                        close = t.price;
+                        close_ts = t.timestamp;
+                    }
+                }
                 let high = window_trades.iter().map(|t| t.price).fold(f64::MIN, f64::max);
                 let low = window_trades.iter().map(|t| t.price).fold(f64::MAX, f64::min);
                 let volume: u64 = window_trades.iter().map(|t| t.size).sum();
@@ -154,6 +238,106 @@ impl TradeBuffer {
         bars
     }
 
+    /// All symbols currently tracked by the buffer, for backfilling
+    /// wildcard subscriptions across every symbol at once.
+    pub fn symbols(&self) -> Vec<String> {
+        self.trades.iter().map(|entry| entry.key().clone()).collect()
+    }
+
+    /// Serialize every symbol's trade queue into a compact, length-prefixed
+    /// binary layout (per symbol: its name, a record count, then fixed-width
+    /// `timestamp:u64, price:f64, size:u64` records), rather than verbose
+    /// JSON, so an operator can checkpoint the buffer periodically or on
+    /// graceful shutdown.
+    pub fn snapshot(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.push(SNAPSHOT_FORMAT_VERSION);
+        out.extend_from_slice(&(self.trades.len() as u32).to_le_bytes());
+
+        for entry in self.trades.iter() {
+            let symbol_bytes = entry.key().as_bytes();
+            out.extend_from_slice(&(symbol_bytes.len() as u16).to_le_bytes());
+            out.extend_from_slice(symbol_bytes);
+
+            let queue = entry.value();
+            out.extend_from_slice(&(queue.len() as u32).to_le_bytes());
+            for trade in queue.iter() {
+                out.extend_from_slice(&trade.timestamp.to_le_bytes());
+                out.extend_from_slice(&trade.price.to_bits().to_le_bytes());
+                out.extend_from_slice(&trade.size.to_le_bytes());
+            }
+        }
+
+        out
+    }
+
+    /// Base64-wrapped form of `snapshot`, for checkpoints carried over a
+    /// text channel instead of raw bytes.
+    pub fn snapshot_base64(&self) -> String {
+        general_purpose::STANDARD.encode(self.snapshot())
+    }
+
+    /// Rebuild the buffer from a `snapshot` payload, replacing its current
+    /// contents. Re-applies the normal age-based pruning against the
+    /// current clock so stale records don't reappear, and rebuilds each
+    /// symbol's deque in snapshot order.
+    pub fn restore(&self, data: &[u8]) -> Result<(), String> {
+        let mut cursor = 0usize;
+
+        let version = *data
+            .first()
+            .ok_or_else(|| "snapshot truncated: missing version byte".to_string())?;
+        cursor += 1;
+        if version != SNAPSHOT_FORMAT_VERSION {
+            return Err(format!("unsupported snapshot format version {}", version));
+        }
+
+        let num_symbols = read_u32(data, &mut cursor)?;
+        let cutoff = current_timestamp_ms().saturating_sub(self.max_age_ms);
+
+        self.trades.clear();
+        self.bytes_in_use.store(0, Ordering::Relaxed);
+
+        for _ in 0..num_symbols {
+            let symbol_len = read_u16(data, &mut cursor)? as usize;
+            let symbol = String::from_utf8(read_bytes(data, &mut cursor, symbol_len)?.to_vec())
+                .map_err(|e| format!("snapshot contains invalid UTF-8 symbol: {}", e))?;
+
+            let num_trades = read_u32(data, &mut cursor)?;
+            let mut queue = VecDeque::with_capacity(num_trades as usize);
+
+            for _ in 0..num_trades {
+                let timestamp = read_u64(data, &mut cursor)?;
+                let price = f64::from_bits(read_u64(data, &mut cursor)?);
+                let size = read_u64(data, &mut cursor)?;
+
+                // Records keep insertion order, which a late trade can
+                // break, so a timestamp older than the cutoff just gets
+                // skipped rather than ending the loop.
+                if timestamp >= cutoff {
+                    queue.push_back(BufferedTrade { timestamp, price, size });
+                }
+            }
+
+            if !queue.is_empty() {
+                self.bytes_in_use
+                    .fetch_add(queue.len() * mem::size_of::<BufferedTrade>(), Ordering::Relaxed);
+                self.trades.insert(symbol, queue);
+            }
+        }
+
+        self.enforce_byte_budget();
+        Ok(())
+    }
+
+    /// Restore from the base64-wrapped form produced by `snapshot_base64`.
+    pub fn restore_base64(&self, data: &str) -> Result<(), String> {
+        let bytes = general_purpose::STANDARD
+            .decode(data)
+            .map_err(|e| format!("invalid base64 snapshot: {}", e))?;
+        self.restore(&bytes)
+    }
+
     /// Get statistics about the buffer
     pub fn stats(&self) -> TradeBufferStats {
         let mut total_trades = 0;
@@ -164,10 +348,16 @@ impl TradeBuffer {
             total_trades += entry.value().len();
         }
 
+        let (allocator_resident_bytes, allocator_allocated_bytes) = read_jemalloc_stats();
+
         TradeBufferStats {
             num_symbols: symbols,
             total_trades,
             max_age_ms: self.max_age_ms,
+            max_bytes: self.max_bytes,
+            estimated_bytes: self.bytes_in_use.load(Ordering::Relaxed),
+            allocator_resident_bytes,
+            allocator_allocated_bytes,
         }
     }
 
@@ -181,6 +371,8 @@ impl TradeBuffer {
             while let Some(front) = queue.front() {
                 if front.timestamp < cutoff {
                     queue.pop_front();
+                    self.bytes_in_use
+                        .fetch_sub(mem::size_of::<BufferedTrade>(), Ordering::Relaxed);
                 } else {
                     break;
                 }
@@ -203,6 +395,16 @@ pub struct TradeBufferStats {
     pub num_symbols: usize,
     pub total_trades: usize,
     pub max_age_ms: u64,
+    /// Total-bytes budget this buffer was constructed with
+    pub max_bytes: usize,
+    /// This buffer's own running `len * size_of::<BufferedTrade>()` estimate
+    pub estimated_bytes: usize,
+    /// Process-wide resident bytes reported by jemalloc, for sizing
+    /// `max_bytes` against real RSS rather than `estimated_bytes` alone.
+    /// `None` if the allocator stats couldn't be read.
+    pub allocator_resident_bytes: Option<usize>,
+    /// Process-wide bytes allocated but not yet freed, per jemalloc.
+    pub allocator_allocated_bytes: Option<usize>,
 }
 
 /// Get current timestamp in milliseconds
@@ -213,6 +415,45 @@ fn current_timestamp_ms() -> u64 {
         .as_millis() as u64
 }
 
+/// Read `len` bytes at `cursor` and advance it, or error if the snapshot is
+/// shorter than the layout it claims to encode.
+fn read_bytes<'a>(data: &'a [u8], cursor: &mut usize, len: usize) -> Result<&'a [u8], String> {
+    let end = cursor.checked_add(len).ok_or("snapshot length overflow")?;
+    let slice = data.get(*cursor..end).ok_or("snapshot truncated")?;
+    *cursor = end;
+    Ok(slice)
+}
+
+fn read_u16(data: &[u8], cursor: &mut usize) -> Result<u16, String> {
+    let bytes = read_bytes(data, cursor, 2)?;
+    Ok(u16::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn read_u32(data: &[u8], cursor: &mut usize) -> Result<u32, String> {
+    let bytes = read_bytes(data, cursor, 4)?;
+    Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn read_u64(data: &[u8], cursor: &mut usize) -> Result<u64, String> {
+    let bytes = read_bytes(data, cursor, 8)?;
+    Ok(u64::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+/// Advance jemalloc's stats epoch and read process-wide resident/allocated
+/// bytes. The epoch advance is what makes the subsequent reads reflect
+/// recent allocator activity rather than a stale cached snapshot.
+fn read_jemalloc_stats() -> (Option<usize>, Option<usize>) {
+    use tikv_jemalloc_ctl::{epoch, stats};
+
+    if epoch::mib().and_then(|mib| mib.advance()).is_err() {
+        return (None, None);
+    }
+
+    let resident = stats::resident::mib().and_then(|mib| mib.read()).ok();
+    let allocated = stats::allocated::mib().and_then(|mib| mib.read()).ok();
+    (resident, allocated)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -298,6 +539,45 @@ mod tests {
         assert_eq!(bars[1].num_trades, 2);
     }
 
+    #[test]
+    fn test_generate_bars_uses_timestamp_order_not_insertion_order() {
+        let buffer = TradeBuffer::with_duration(60_000);
+
+        // Stored out of timestamp order: the window's last trade (by time)
+        // arrives first, its first trade arrives last. Open/close must
+        // still reflect chronological order, not insertion order.
+        let base_time = 1000000;
+        buffer.store(&make_trade("MGRX", base_time + 180, 1.65, 800));
+        buffer.store(&make_trade("MGRX", base_time + 50, 1.60, 500));
+        buffer.store(&make_trade("MGRX", base_time + 120, 1.62, 200));
+
+        let bars = buffer.generate_bars_since("MGRX", 250, base_time);
+
+        assert_eq!(bars.len(), 1);
+        assert_eq!(bars[0].open, 1.60);
+        assert_eq!(bars[0].close, 1.65);
+        assert_eq!(bars[0].high, 1.65);
+        assert_eq!(bars[0].low, 1.60);
+    }
+
+    #[test]
+    fn test_generate_bars_tie_break_matches_window_add_trade() {
+        let buffer = TradeBuffer::with_duration(60_000);
+
+        // Two trades at the identical millisecond: the later-stored one
+        // must win both open and close, same as `Window::add_trade`'s
+        // <=/>= tie-break.
+        let base_time = 1000000;
+        buffer.store(&make_trade("MGRX", base_time + 50, 1.60, 500));
+        buffer.store(&make_trade("MGRX", base_time + 50, 1.70, 500));
+
+        let bars = buffer.generate_bars_since("MGRX", 250, base_time);
+
+        assert_eq!(bars.len(), 1);
+        assert_eq!(bars[0].open, 1.70);
+        assert_eq!(bars[0].close, 1.70);
+    }
+
     #[test]
     fn test_multiple_symbols() {
         let buffer = TradeBuffer::with_duration(60_000);
@@ -315,4 +595,83 @@ mod tests {
         assert_eq!(stats.num_symbols, 3);
         assert_eq!(stats.total_trades, 3);
     }
+
+    #[test]
+    fn test_byte_budget_evicts_oldest_across_symbols() {
+        let trade_size = mem::size_of::<BufferedTrade>();
+        // Budget for exactly 2 trades across all symbols
+        let buffer = TradeBuffer::with_limits(60_000, trade_size * 2);
+
+        buffer.store(&make_trade("AAPL", 1000, 150.0, 100));
+        buffer.store(&make_trade("MGRX", 2000, 1.60, 500));
+        buffer.store(&make_trade("TSLA", 3000, 250.0, 50));
+
+        let stats = buffer.stats();
+        assert_eq!(stats.total_trades, 2);
+        assert_eq!(stats.estimated_bytes, trade_size * 2);
+
+        // The globally oldest trade (AAPL @ 1000) should have been evicted
+        assert_eq!(buffer.get_trades_since("AAPL", 0).len(), 0);
+        assert_eq!(buffer.get_trades_since("MGRX", 0).len(), 1);
+        assert_eq!(buffer.get_trades_since("TSLA", 0).len(), 1);
+    }
+
+    #[test]
+    fn test_snapshot_restore_round_trip() {
+        let buffer = TradeBuffer::with_duration(60_000);
+        buffer.store(&make_trade("AAPL", 1000, 150.0, 100));
+        buffer.store(&make_trade("AAPL", 2000, 151.0, 200));
+        buffer.store(&make_trade("MGRX", 1500, 1.60, 500));
+
+        let snapshot = buffer.snapshot();
+
+        let restored = TradeBuffer::with_duration(60_000);
+        restored.restore(&snapshot).unwrap();
+
+        let aapl = restored.get_trades_since("AAPL", 0);
+        assert_eq!(aapl.len(), 2);
+        assert_eq!(aapl[0].timestamp, 1000);
+        assert_eq!(aapl[1].timestamp, 2000);
+        assert_eq!(restored.get_trades_since("MGRX", 0).len(), 1);
+
+        let stats = restored.stats();
+        assert_eq!(stats.num_symbols, 2);
+        assert_eq!(stats.total_trades, 3);
+    }
+
+    #[test]
+    fn test_snapshot_base64_round_trip() {
+        let buffer = TradeBuffer::with_duration(60_000);
+        buffer.store(&make_trade("AAPL", 1000, 150.0, 100));
+
+        let encoded = buffer.snapshot_base64();
+
+        let restored = TradeBuffer::with_duration(60_000);
+        restored.restore_base64(&encoded).unwrap();
+
+        assert_eq!(restored.get_trades_since("AAPL", 0).len(), 1);
+    }
+
+    #[test]
+    fn test_restore_prunes_stale_records_against_current_clock() {
+        let buffer = TradeBuffer::with_duration(60_000);
+        // A trade from the far past should be dropped on restore, since
+        // restore re-applies age-based pruning against the current clock
+        // rather than the clock at snapshot time.
+        buffer.store(&make_trade("AAPL", 1, 150.0, 100));
+        let snapshot = buffer.snapshot();
+
+        let restored = TradeBuffer::with_duration(60_000);
+        restored.restore(&snapshot).unwrap();
+
+        assert_eq!(restored.get_trades_since("AAPL", 0).len(), 0);
+        assert_eq!(restored.stats().num_symbols, 0);
+    }
+
+    #[test]
+    fn test_restore_rejects_truncated_snapshot() {
+        let buffer = TradeBuffer::new();
+        let result = buffer.restore(&[SNAPSHOT_FORMAT_VERSION, 1, 0, 0, 0]);
+        assert!(result.is_err());
+    }
 }
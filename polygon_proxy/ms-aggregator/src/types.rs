@@ -90,6 +90,28 @@ pub struct MsBar {
 pub struct SubscriptionMessage {
     pub action: String,
     pub params: String,
+    /// For `subscribe`: backfill already-closed bars from the trade buffer
+    /// since this timestamp (ms) alongside the subscription acknowledgement.
+    #[serde(default)]
+    pub since: Option<u64>,
+}
+
+/// A symbol currently being aggregated, with its active interval sizes.
+/// Returned by the `getMarkets` action.
+#[derive(Debug, Clone, Serialize)]
+pub struct MarketInfo {
+    pub symbol: String,
+    pub intervals_ms: Vec<u64>,
+}
+
+/// Payload sent to a connected client. Untagged so `Bars` serializes as a
+/// plain array of [`MsBar`], preserving the existing wire format; `Markets`
+/// is only ever sent in response to `getMarkets`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum OutboundPayload {
+    Bars(Vec<MsBar>),
+    Markets(Vec<MarketInfo>),
 }
 
 /// Authentication message
@@ -97,6 +119,31 @@ pub struct SubscriptionMessage {
 pub struct AuthMessage {
     pub action: String,
     pub params: String,
+    /// Binary wire format to negotiate for all frames after this one:
+    /// `"msgpack"` or `"bincode"`. Omitted (or unrecognized) keeps JSON text frames.
+    #[serde(default)]
+    pub encoding: Option<String>,
+}
+
+/// Wire encoding negotiated via [`AuthMessage::encoding`]. Once a client
+/// negotiates a binary encoding, every frame after the (always-JSON) auth
+/// frame must use it: bars go out as `Message::Binary`, and subscribe/
+/// unsubscribe frames are decoded from binary too.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientEncoding {
+    Json,
+    MsgPack,
+    Bincode,
+}
+
+impl ClientEncoding {
+    pub fn parse(raw: Option<&str>) -> Self {
+        match raw {
+            Some("msgpack") => ClientEncoding::MsgPack,
+            Some("bincode") => ClientEncoding::Bincode,
+            _ => ClientEncoding::Json,
+        }
+    }
 }
 
 /// Bar subscription key
@@ -1,10 +1,14 @@
 use crate::config::Config;
+use crate::metrics::Metrics;
 use crate::types::{PolygonMessage, PolygonTrade};
 use anyhow::{Context, Result};
 use futures_util::{SinkExt, StreamExt};
 use serde_json::json;
+use std::collections::HashSet;
+use std::sync::Arc;
 use tokio::net::TcpStream;
 use tokio::sync::mpsc;
+use tokio::time::{Duration, Instant};
 use tokio_tungstenite::{connect_async, tungstenite::Message, MaybeTlsStream, WebSocketStream};
 use tracing::{debug, error, info, warn};
 
@@ -13,16 +17,30 @@ type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
 pub struct UpstreamConnection {
     config: Config,
     trade_tx: mpsc::Sender<PolygonTrade>,
+    /// Subscription strings currently active upstream, replayed after every reconnect.
+    active_subscriptions: HashSet<String>,
+    metrics: Arc<Metrics>,
 }
 
 impl UpstreamConnection {
-    pub fn new(config: Config, trade_tx: mpsc::Sender<PolygonTrade>) -> Self {
-        Self { config, trade_tx }
+    pub fn new(config: Config, trade_tx: mpsc::Sender<PolygonTrade>, metrics: Arc<Metrics>) -> Self {
+        let mut active_subscriptions = HashSet::new();
+        active_subscriptions.insert("T.*".to_string());
+
+        Self {
+            config,
+            trade_tx,
+            active_subscriptions,
+            metrics,
+        }
     }
 
     pub async fn run(&self) -> Result<()> {
+        let mut attempt: u32 = 0;
+
         loop {
             info!("Connecting to firehose at {}", self.config.firehose_url);
+            let connected_at = Instant::now();
 
             match self.connect_and_run().await {
                 Ok(_) => {
@@ -33,9 +51,24 @@ impl UpstreamConnection {
                 }
             }
 
-            // Reconnect after delay
-            info!("Reconnecting to firehose in 5 seconds...");
-            tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
+            if connected_at.elapsed() >= Duration::from_secs(self.config.reconnect_stable_after_secs)
+            {
+                attempt = 0;
+            } else {
+                attempt += 1;
+            }
+
+            let delay = backoff_with_jitter(
+                attempt,
+                self.config.reconnect_base_ms,
+                self.config.reconnect_max_ms,
+            );
+            self.metrics.record_reconnect();
+            warn!(
+                "Reconnecting to firehose in {:?} (attempt {})",
+                delay, attempt
+            );
+            tokio::time::sleep(delay).await;
         }
     }
 
@@ -44,6 +77,12 @@ impl UpstreamConnection {
             .await
             .context("Failed to connect to firehose")?;
 
+        if let MaybeTlsStream::Plain(tcp) = ws_stream.get_ref() {
+            if let Err(e) = tcp.set_nodelay(true) {
+                warn!("Failed to set TCP_NODELAY on upstream socket: {}", e);
+            }
+        }
+
         info!("Connected to firehose proxy");
 
         let (mut write, mut read) = ws_stream.split();
@@ -51,10 +90,10 @@ impl UpstreamConnection {
         // Note: Firehose proxy doesn't require authentication for internal connections
         // It broadcasts data to all connected clients immediately
 
-        // Subscribe to all trades (T.*)
+        // Replay the full subscription set so a reconnect resumes the same feed.
         let subscribe_msg = json!({
             "action": "subscribe",
-            "params": "T.*"
+            "params": self.active_subscriptions.iter().cloned().collect::<Vec<_>>().join(",")
         });
 
         write
@@ -67,26 +106,43 @@ impl UpstreamConnection {
         // Process incoming messages
         info!("Starting message receive loop...");
         let mut msg_count = 0;
-        while let Some(msg) = read.next().await {
-            let msg = msg.context("WebSocket error")?;
-
-            match msg {
-                Message::Text(text) => {
-                    msg_count += 1;
-                    if msg_count % 1000 == 0 {
-                        info!("Received {} messages from firehose so far", msg_count);
+        let idle_timeout = Duration::from_secs(self.config.upstream_idle_timeout_secs);
+        let mut last_message_at = Instant::now();
+
+        loop {
+            tokio::select! {
+                msg = read.next() => {
+                    let Some(msg) = msg else { break };
+                    let msg = msg.context("WebSocket error")?;
+                    last_message_at = Instant::now();
+
+                    match msg {
+                        Message::Text(text) => {
+                            msg_count += 1;
+                            if msg_count % 1000 == 0 {
+                                info!("Received {} messages from firehose so far", msg_count);
+                            }
+                            debug!("RAW MESSAGE: {}", &text[..text.len().min(100)]);
+                            self.handle_message(&text).await;
+                        }
+                        Message::Close(_) => {
+                            info!("Firehose closed connection");
+                            break;
+                        }
+                        Message::Ping(data) => {
+                            write.send(Message::Pong(data)).await.ok();
+                        }
+                        _ => {}
                     }
-                    debug!("RAW MESSAGE: {}", &text[..text.len().min(100)]);
-                    self.handle_message(&text).await;
                 }
-                Message::Close(_) => {
-                    info!("Firehose closed connection");
-                    break;
-                }
-                Message::Ping(data) => {
-                    write.send(Message::Pong(data)).await.ok();
+                _ = tokio::time::sleep(idle_timeout.saturating_sub(last_message_at.elapsed())) => {
+                    if last_message_at.elapsed() >= idle_timeout {
+                        return Err(anyhow::anyhow!(
+                            "Firehose socket idle for {:?}, treating as wedged",
+                            idle_timeout
+                        ));
+                    }
                 }
-                _ => {}
             }
         }
 
@@ -137,3 +193,15 @@ impl UpstreamConnection {
         }
     }
 }
+
+/// `delay = min(cap, base * 2^attempt)`, then uniform jitter in `[0, delay]`.
+fn backoff_with_jitter(attempt: u32, base_ms: u64, cap_ms: u64) -> Duration {
+    let exp = base_ms.saturating_mul(2u64.saturating_pow(attempt));
+    let delay_ms = exp.min(cap_ms);
+    let jittered = if delay_ms == 0 {
+        0
+    } else {
+        rand::random::<u64>() % (delay_ms + 1)
+    };
+    Duration::from_millis(jittered)
+}